@@ -0,0 +1,182 @@
+use crate::bytecode::Address;
+use std::collections::HashMap;
+
+//counters exposed to the host through VirtualMachine::alloc_stats
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct AllocStats {
+    pub allocations: usize,
+    pub frees: usize,
+    pub reallocations: usize,
+    pub failures: usize,
+    pub live_blocks: usize,
+    pub live_slots: usize,
+    pub peak_slots: usize,
+}
+
+//bump allocator with a first-fit free list over heap slots [base, limit)
+pub struct Allocator {
+    top: Address,
+    limit: Address,
+    free: Vec<(Address, usize)>,        //free blocks below top, sorted by address
+    blocks: HashMap<Address, usize>,    //live blocks and their sizes
+    poisoned: Option<Vec<bool>>,        //debug mode: slots of freed blocks
+    stats: AllocStats,
+}
+
+impl Allocator {
+    pub fn new(base: Address, limit: Address, debug: bool) -> Self {
+        let poisoned = if debug { Some(vec![false; limit]) } else { None };
+        Allocator { top: base, limit, free: Vec::new(), blocks: HashMap::new(), poisoned, stats: AllocStats::default() }
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        self.stats
+    }
+
+    //true if the slot belongs to a freed block (debug mode only)
+    pub fn is_poisoned(&self, slot: Address) -> bool {
+        match &self.poisoned {
+            Some(p) => p.get(slot).copied().unwrap_or(false),
+            _ => false
+        }
+    }
+
+    //true if addr is the start of a block that was freed and not reused
+    pub fn is_freed(&self, addr: Address) -> bool {
+        !self.blocks.contains_key(&addr) && self.is_poisoned(addr)
+    }
+
+    pub fn size_of(&self, addr: Address) -> Option<usize> {
+        self.blocks.get(&addr).copied()
+    }
+
+    pub fn alloc(&mut self, size: usize) -> Option<Address> {
+        if size == 0 {
+            self.stats.failures += 1;
+            return None;
+        }
+        let addr = match self.free.iter().position(|&(_, s)| s >= size) {
+            Some(i) => {
+                let (start, s) = self.free[i];
+                if s == size {
+                    self.free.remove(i);
+                } else {
+                    self.free[i] = (start + size, s - size);
+                }
+                start
+            },
+            _ => {
+                if self.limit.saturating_sub(self.top) < size {
+                    self.stats.failures += 1;
+                    return None;
+                }
+                self.top += size;
+                self.top - size
+            }
+        };
+        self.mark(addr, size, false);
+        self.blocks.insert(addr, size);
+        self.stats.allocations += 1;
+        self.stats.live_blocks += 1;
+        self.stats.live_slots += size;
+        self.stats.peak_slots = self.stats.peak_slots.max(self.stats.live_slots);
+        Some(addr)
+    }
+
+    pub fn free(&mut self, addr: Address) -> bool {
+        let size = match self.blocks.remove(&addr) {
+            Some(size) => size,
+            _ => return false
+        };
+        self.mark(addr, size, true);
+        self.release(addr, size);
+        self.stats.frees += 1;
+        self.stats.live_blocks -= 1;
+        self.stats.live_slots -= size;
+        true
+    }
+
+    //returns the new address of the block; the caller copies the contents when it moved
+    pub fn realloc(&mut self, addr: Address, size: usize) -> Option<Address> {
+        let old = match self.blocks.get(&addr) {
+            Some(&old) if size > 0 => old,
+            _ => {
+                self.stats.failures += 1;
+                return None;
+            }
+        };
+        self.stats.reallocations += 1;
+        if size <= old {
+            self.blocks.insert(addr, size);
+            if size < old {
+                self.mark(addr + size, old - size, true);
+                self.release(addr + size, old - size);
+                self.stats.live_slots -= old - size;
+            }
+            return Some(addr);
+        }
+        if self.grow(addr + old, size - old) {
+            self.mark(addr + old, size - old, false);
+            self.blocks.insert(addr, size);
+            self.stats.live_slots += size - old;
+            self.stats.peak_slots = self.stats.peak_slots.max(self.stats.live_slots);
+            return Some(addr);
+        }
+        let new = self.alloc(size)?;
+        self.stats.allocations -= 1;
+        self.free(addr);
+        self.stats.frees -= 1;
+        Some(new)
+    }
+
+    //claims `extra` slots starting at `end` from the free block there or from the bump region
+    fn grow(&mut self, end: Address, extra: usize) -> bool {
+        if end == self.top {
+            if self.limit - self.top < extra {
+                return false;
+            }
+            self.top += extra;
+            return true;
+        }
+        let i = self.free.partition_point(|&(start, _)| start < end);
+        match self.free.get(i) {
+            Some(&(start, s)) if start == end && s >= extra => {
+                if s == extra {
+                    self.free.remove(i);
+                } else {
+                    self.free[i] = (start + extra, s - extra);
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    //returns a block to the free list, merging it with its neighbours and the bump region
+    fn release(&mut self, addr: Address, size: usize) {
+        let i = self.free.partition_point(|&(start, _)| start < addr);
+        self.free.insert(i, (addr, size));
+        if i + 1 < self.free.len() && self.free[i].0 + self.free[i].1 == self.free[i + 1].0 {
+            self.free[i].1 += self.free[i + 1].1;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == self.free[i].0 {
+            self.free[i - 1].1 += self.free[i].1;
+            self.free.remove(i);
+        }
+        if let Some(&(start, s)) = self.free.last() {
+            if start + s == self.top {
+                self.top = start;
+                self.free.pop();
+            }
+        }
+    }
+
+    fn mark(&mut self, addr: Address, size: usize, freed: bool) {
+        if let Some(p) = &mut self.poisoned {
+            for slot in &mut p[addr..addr + size] {
+                *slot = freed;
+            }
+        }
+    }
+}
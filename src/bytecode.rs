@@ -1,8 +1,8 @@
 use std::mem;
-use std::convert::TryInto;
-use derive_more::*;
+use std::convert::{TryFrom, TryInto};
+use std::cmp::Ordering;
 
-#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Add, Sub)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Immediate {
     None(),
     U8(u8),
@@ -13,14 +13,137 @@ pub enum Immediate {
     I32(i32),
     U64(u64),
     I64(i64),
+    U128(u128),
+    I128(i128),
     F32(f32),
-    F64(f64)
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Ref(usize),     //reference into the garbage collected object heap
+    Str(usize)      //string object in the garbage collected object heap
+}
+
+impl Immediate {
+    //mathematical value of an integer immediate
+    pub(crate) fn as_i128(self) -> Option<i128> {
+        match self {
+            Immediate::U8(v) => Some(v as i128),
+            Immediate::I8(v) => Some(v as i128),
+            Immediate::U16(v) => Some(v as i128),
+            Immediate::I16(v) => Some(v as i128),
+            Immediate::U32(v) => Some(v as i128),
+            Immediate::I32(v) => Some(v as i128),
+            Immediate::U64(v) => Some(v as i128),
+            Immediate::I64(v) => Some(v as i128),
+            Immediate::U128(v) => i128::try_from(v).ok(),
+            Immediate::I128(v) => Some(v),
+            _ => None
+        }
+    }
+
+    //two's complement image of an integer immediate widened to 128 bits
+    pub(crate) fn as_bits128(self) -> Option<u128> {
+        match self {
+            Immediate::U128(v) => Some(v),
+            _ => self.as_i128().map(|v| v as u128)
+        }
+    }
+
+    //bit pattern of an integer immediate read as unsigned at its own width
+    fn as_u128_bits(self) -> Option<u128> {
+        match self {
+            Immediate::U8(v) => Some(v as u128),
+            Immediate::I8(v) => Some(v as u8 as u128),
+            Immediate::U16(v) => Some(v as u128),
+            Immediate::I16(v) => Some(v as u16 as u128),
+            Immediate::U32(v) => Some(v as u128),
+            Immediate::I32(v) => Some(v as u32 as u128),
+            Immediate::U64(v) => Some(v as u128),
+            Immediate::I64(v) => Some(v as u64 as u128),
+            Immediate::U128(v) => Some(v),
+            Immediate::I128(v) => Some(v as u128),
+            _ => None
+        }
+    }
+
+    fn as_f64(self) -> Option<f64> {
+        match self {
+            Immediate::F32(v) => Some(v as f64),
+            Immediate::F64(v) => Some(v),
+            Immediate::U128(v) => Some(v as f64),
+            _ => self.as_i128().map(|v| v as f64)
+        }
+    }
+
+    //orders two integer immediates, including U128 values beyond the range of i128
+    fn cmp_integer(self, other: Immediate) -> Option<Ordering> {
+        match (self, other) {
+            (Immediate::U128(v), Immediate::U128(u)) => Some(v.cmp(&u)),
+            (Immediate::U128(v), _) => match other.as_i128()? {
+                u if u < 0 => Some(Ordering::Greater),
+                u => Some(v.cmp(&(u as u128)))
+            },
+            (_, Immediate::U128(_)) => other.cmp_integer(self).map(Ordering::reverse),
+            _ => Some(self.as_i128()?.cmp(&other.as_i128()?))
+        }
+    }
+
+    //compares by value regardless of variant, None if unordered (NaN or None())
+    pub fn cmp_signed(self, other: Immediate) -> Option<Ordering> {
+        match (self, other) {
+            (Immediate::None(), Immediate::None()) => Some(Ordering::Equal),
+            (Immediate::None(), _) | (_, Immediate::None()) => None,
+            (Immediate::Bool(v), Immediate::Bool(u)) => Some(v.cmp(&u)),
+            (Immediate::Char(v), Immediate::Char(u)) => Some(v.cmp(&u)),
+            //references only compare by identity
+            (Immediate::Ref(v), Immediate::Ref(u)) | (Immediate::Str(v), Immediate::Str(u)) => Some(v.cmp(&u)),
+            (Immediate::Ref(_), _) | (_, Immediate::Ref(_)) | (Immediate::Str(_), _) | (_, Immediate::Str(_)) => None,
+            _ => match self.cmp_integer(other) {
+                Some(ordering) => Some(ordering),
+                _ => self.as_f64()?.partial_cmp(&other.as_f64()?)
+            }
+        }
+    }
+
+    //compares integers as unsigned bit patterns, floats by value
+    pub fn cmp_unsigned(self, other: Immediate) -> Option<Ordering> {
+        match (self.as_u128_bits(), other.as_u128_bits()) {
+            (Some(v), Some(u)) => Some(v.cmp(&u)),
+            _ => self.cmp_signed(other)
+        }
+    }
 }
 
 pub type Register = usize;
 pub type Address = usize;
 
-#[derive(Debug, Copy, Clone)]
+//heap operand of the VSTORE/VLOAD family
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HeapAddress {
+    Direct(Address),                    //literal address
+    Indirect(Register),                 //address held in register
+    Offset(Register, isize),            //base register + immediate offset
+    Indexed(Register, Register, usize), //base register + index register * scale
+}
+
+impl HeapAddress {
+    pub fn registers(&self) -> Vec<Register> {
+        match *self {
+            HeapAddress::Direct(_) => Vec::new(),
+            HeapAddress::Indirect(base) | HeapAddress::Offset(base, _) => vec![base],
+            HeapAddress::Indexed(base, index, _) => vec![base, index],
+        }
+    }
+}
+
+//immediate branch target, relative targets count instructions from the next instruction
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    Absolute(Address),
+    Relative(isize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Instruction {
     NOP(),                          //do nothing
     MOV(Register, Immediate),       //mov immediate to reg
@@ -30,13 +153,69 @@ pub enum Instruction {
     JNE(Register),                  //Jump if not equal to location
     JG(Register),                   //Jump if greater than
     JL(Register),                   //Jump if less than
+    JGE(Register),                  //Jump if greater than or equal
+    JLE(Register),                  //Jump if less than or equal
+    JA(Register),                   //Jump if above (unsigned greater than)
+    JB(Register),                   //Jump if below (unsigned less than)
+    CMOVE(Register, Register),      //mov reg2 to reg1 if equal
+    CMOVNE(Register, Register),     //mov reg2 to reg1 if not equal
+    CMOVG(Register, Register),      //mov reg2 to reg1 if greater than
+    CMOVL(Register, Register),      //mov reg2 to reg1 if less than
+    CMOVGE(Register, Register),     //mov reg2 to reg1 if greater than or equal
+    CMOVLE(Register, Register),     //mov reg2 to reg1 if less than or equal
+    CMOVA(Register, Register),      //mov reg2 to reg1 if above
+    CMOVB(Register, Register),      //mov reg2 to reg1 if below
+    CMOVU(Register, Register),      //mov reg2 to reg1 if unordered
+    SETE(Register),                 //set reg to Bool(true) if equal, else Bool(false)
+    SETNE(Register),                //set reg to Bool(true) if not equal, else Bool(false)
+    SETG(Register),                 //set reg to Bool(true) if greater than, else Bool(false)
+    SETL(Register),                 //set reg to Bool(true) if less than, else Bool(false)
+    SETGE(Register),                //set reg to Bool(true) if greater than or equal, else Bool(false)
+    SETLE(Register),                //set reg to Bool(true) if less than or equal, else Bool(false)
+    SETA(Register),                 //set reg to Bool(true) if above, else Bool(false)
+    SETB(Register),                 //set reg to Bool(true) if below, else Bool(false)
+    SETU(Register),                 //set reg to Bool(true) if unordered, else Bool(false)
+    DUP(),                          //duplicates top of stack
+    SWAP(),                         //swaps the top two stack items
+    OVER(),                         //copies second item to top of stack
+    ROT(),                          //rotates third item to top of stack
+    DROP(),                         //discards top of stack
+    PICK(usize),                    //copies the nth item (0 = top) to top of stack
+    SADD(),                         //pops 2 items, pushes their sum
+    SSUB(),                         //pops 2 items, pushes second minus top
+    SMUL(),                         //pops 2 items, pushes their product
+    SDIV(),                         //pops 2 items, pushes second divided by top
+    SCMP(),                         //pops 2 items, compares second with top
+    SAND(),                         //pops 2 items, pushes bitwise AND
+    SOR(),                          //pops 2 items, pushes bitwise OR
+    SXOR(),                         //pops 2 items, pushes bitwise XOR
+    SSHR(),                         //pops 2 items, shifts second right by top
+    SSHL(),                         //pops 2 items, shifts second left by top
+    ADD3(Register, Register, Register), //reg1 = reg2 + reg3
+    SUB3(Register, Register, Register), //reg1 = reg2 - reg3
+    MUL3(Register, Register, Register), //reg1 = reg2 * reg3
+    DIV3(Register, Register, Register), //reg1 = reg2 / reg3
+    AND3(Register, Register, Register), //reg1 = reg2 & reg3
+    OR3(Register, Register, Register), //reg1 = reg2 | reg3
+    XOR3(Register, Register, Register), //reg1 = reg2 ^ reg3
+    SHR3(Register, Register, Register), //reg1 = reg2 >> reg3
+    SHL3(Register, Register, Register), //reg1 = reg2 << reg3
+    ADDI(Register, Immediate),      //reg = reg + immediate
+    SUBI(Register, Immediate),      //reg = reg - immediate
+    MULI(Register, Immediate),      //reg = reg * immediate
+    DIVI(Register, Immediate),      //reg = reg / immediate
+    ANDI(Register, Immediate),      //reg = reg & immediate
+    ORI(Register, Immediate),       //reg = reg | immediate
+    XORI(Register, Immediate),      //reg = reg ^ immediate
+    SHRI(Register, Immediate),      //reg = reg >> immediate
+    SHLI(Register, Immediate),      //reg = reg << immediate
     CMP(Register, Register),        //Compares two registers
     PRINTR(Register),               //print contents of register
-    PRINTV(Address),                //print contents of immediate at address
-    VSTORE(Address, Immediate),     //store immediate into VMHeap at specific address from stack
-    VLOAD(Address),                 //load immediate from VMHeap and pushes value to stack
-    VSTORER(Address, Register),     //store immediate into VMHeap from register contents
-    VLOADR(Register, Address),      //loads a immediate from VMHeap to register
+    PRINTV(HeapAddress),            //print contents of immediate at address
+    VSTORE(HeapAddress, Immediate), //store immediate into VMHeap at specific address from stack
+    VLOAD(HeapAddress),             //load immediate from VMHeap and pushes value to stack
+    VSTORER(HeapAddress, Register), //store immediate into VMHeap from register contents
+    VLOADR(Register, HeapAddress),  //loads a immediate from VMHeap to register
     ADD(Register, Register),        //Add 2 registers and pushes result on stack
     SUB(Register, Register),        //Subtract 2 registers and pushes result on stack
     MUL(Register, Register),        //Multiple 2 registers and pushes result on stack
@@ -52,14 +231,348 @@ pub enum Instruction {
     CALL(Register),                 //call functon at address in register
     RET(),                          //return from routine
     HALT(),                         //bye bye
+    FSQRT(Register),                //Square root of float register. pushes result on stack
+    FMIN(Register, Register),       //Minimum of 2 float registers. pushes result on stack
+    FMAX(Register, Register),       //Maximum of 2 float registers. pushes result on stack
+    FLOOR(Register),                //Rounds float register down. pushes result on stack
+    CEIL(Register),                 //Rounds float register up. pushes result on stack
+    TRUNC(Register),                //Rounds float register toward zero. pushes result on stack
+    ROUND(Register),                //Rounds float register to nearest, ties away from zero. pushes result on stack
+    FMA(Register, Register, Register), //Fused multiply-add reg1 * reg2 + reg3. pushes result on stack
+    JU(Register),                   //Jump if unordered (last CMP involved a NaN)
+    ENTER(usize),                   //allocates n local slots in the current frame
+    LEAVE(),                        //releases the local slots of the current frame
+    LOADLOCAL(Register, usize),     //loads local slot n of the current frame into register
+    STORELOCAL(usize, Register),    //stores register into local slot n of the current frame
+    LOADARG(Register, usize),       //loads argument n (0 = last pushed) of the current frame into register
+    PUSHA(Register, Register),      //pushes registers reg1..=reg2 on the stack
+    POPA(Register, Register),       //pops registers reg2 down to reg1 from the stack
+    TAILCALL(Register, usize),      //replaces the current frame with a call to address in register, passing n arguments
+    JMPI(Target),                   //jump to immediate target
+    JEI(Target),                    //Jump if equal to immediate target
+    JNEI(Target),                   //Jump if not equal to immediate target
+    JGI(Target),                    //Jump if greater than to immediate target
+    JLI(Target),                    //Jump if less than to immediate target
+    JGEI(Target),                   //Jump if greater than or equal to immediate target
+    JLEI(Target),                   //Jump if less than or equal to immediate target
+    JAI(Target),                    //Jump if above to immediate target
+    JBI(Target),                    //Jump if below to immediate target
+    JUI(Target),                    //Jump if unordered to immediate target
+    CALLI(Target),                  //call function at immediate target
+    TAILCALLI(Target, usize),       //tail call to immediate target, passing n arguments
+    SWITCH(Register, usize),        //jumps through the table of n CASE entries that follows, by index in register
+    CASE(Target),                   //jump table entry of a SWITCH; the first entry is the default target
+    LOAD8U(Register, Register),     //loads U8 from linear memory at address in reg2 into reg1
+    LOAD8S(Register, Register),     //loads I8 from linear memory at address in reg2 into reg1
+    LOAD16U(Register, Register),    //loads U16 from linear memory at address in reg2 into reg1
+    LOAD16S(Register, Register),    //loads I16 from linear memory at address in reg2 into reg1
+    LOAD32U(Register, Register),    //loads U32 from linear memory at address in reg2 into reg1
+    LOAD32S(Register, Register),    //loads I32 from linear memory at address in reg2 into reg1
+    LOAD64U(Register, Register),    //loads U64 from linear memory at address in reg2 into reg1
+    LOAD64S(Register, Register),    //loads I64 from linear memory at address in reg2 into reg1
+    LOADF32(Register, Register),    //loads F32 from linear memory at address in reg2 into reg1
+    LOADF64(Register, Register),    //loads F64 from linear memory at address in reg2 into reg1
+    STORE8(Register, Register),     //stores integer in reg2 truncated to 8 bits to linear memory at address in reg1
+    STORE16(Register, Register),    //stores integer in reg2 truncated to 16 bits to linear memory at address in reg1
+    STORE32(Register, Register),    //stores integer in reg2 truncated to 32 bits to linear memory at address in reg1
+    STORE64(Register, Register),    //stores integer in reg2 truncated to 64 bits to linear memory at address in reg1
+    STOREF32(Register, Register),   //stores F32 in reg2 to linear memory at address in reg1
+    STOREF64(Register, Register),   //stores F64 in reg2 to linear memory at address in reg1
+    ALLOC(Register, Register),      //allocates reg2 heap slots, address into reg1
+    FREE(Register),                 //frees the heap block at address in reg
+    REALLOC(Register, Register),    //resizes the heap block at address in reg1 to reg2 slots, new address into reg1
+    NEWOBJ(Register, Register),     //allocates a garbage collected object with reg2 fields, reference into reg1
+    GETFIELD(Register, Register, usize), //loads field n of the object referenced by reg2 into reg1
+    SETFIELD(Register, usize, Register), //stores reg2 into field n of the object referenced by reg1
+    LDSTR(Register, usize),         //loads string n of the constant pool into register
+    STRCAT(Register, Register, Register), //reg1 = reg2 followed by reg3
+    STRLEN(Register, Register),     //reg1 = number of chars in string reg2
+    SUBSTR(Register, Register, Register, Register), //reg1 = reg4 chars of string reg2 starting at char reg3
+    STRCMP(Register, Register),     //compares two strings lexicographically and sets the flags
+    CHARAT(Register, Register, Register), //reg1 = char reg3 of string reg2
+    TOSTR(Register, Register),      //reg1 = decimal text of the integer or float in reg2
+    PARSEI(Register, Register),     //reg1 = I64 parsed from string reg2
+    PARSEF(Register, Register),     //reg1 = F64 parsed from string reg2
+    PRINTS(Register),               //writes string in register to the output
+    NEWARRAY(Register, Immediate, Register), //reg1 = new array of reg2 elements filled with immediate, whose type the elements keep; None() makes an array of references
+    ALOAD(Register, Register, Register), //reg1 = element reg3 of array reg2
+    ASTORE(Register, Register, Register), //element reg2 of array reg1 = reg3
+    ALEN(Register, Register),       //reg1 = length of array reg2
+    ACOPY(Register, Register, Register, Register, Register), //copies reg5 elements of array reg3 from index reg4 to array reg1 at index reg2
+    AFILL(Register, Register),      //sets every element of array reg1 to reg2
+    LOADMOD(Register, Register),    //maps the host module named by string reg2 into the VM, reg1 = whether it succeeded
+    LOADSYM(Register, Register),    //reg1 = address of the exported symbol named by string reg2
+}
+
+impl Instruction {
+    //register operands of the instruction, used to validate them against the register file
+    pub fn registers(&self) -> Vec<Register> {
+        match *self {
+            Instruction::PRINTV(addr)
+            | Instruction::VSTORE(addr, _)
+            | Instruction::VLOAD(addr) => addr.registers(),
+            Instruction::VSTORER(addr, reg)
+            | Instruction::VLOADR(reg, addr) => {
+                let mut regs = addr.registers();
+                regs.push(reg);
+                regs
+            },
+            Instruction::NOP()
+            | Instruction::VPUSH(_)
+            | Instruction::RET()
+            | Instruction::HALT()
+            | Instruction::DUP()
+            | Instruction::SWAP()
+            | Instruction::OVER()
+            | Instruction::ROT()
+            | Instruction::DROP()
+            | Instruction::PICK(_)
+            | Instruction::SADD()
+            | Instruction::SSUB()
+            | Instruction::SMUL()
+            | Instruction::SDIV()
+            | Instruction::SCMP()
+            | Instruction::SAND()
+            | Instruction::SOR()
+            | Instruction::SXOR()
+            | Instruction::SSHR()
+            | Instruction::SSHL()
+            | Instruction::ENTER(_)
+            | Instruction::LEAVE()
+            | Instruction::JMPI(_)
+            | Instruction::JEI(_)
+            | Instruction::JNEI(_)
+            | Instruction::JGI(_)
+            | Instruction::JLI(_)
+            | Instruction::JGEI(_)
+            | Instruction::JLEI(_)
+            | Instruction::JAI(_)
+            | Instruction::JBI(_)
+            | Instruction::JUI(_)
+            | Instruction::CALLI(_)
+            | Instruction::TAILCALLI(_, _)
+            | Instruction::CASE(_) => Vec::new(),
+            Instruction::MOV(register, _) => vec![register],
+            Instruction::MOVR(reg1, reg2)
+            | Instruction::CMP(reg1, reg2)
+            | Instruction::ADD(reg1, reg2)
+            | Instruction::SUB(reg1, reg2)
+            | Instruction::MUL(reg1, reg2)
+            | Instruction::DIV(reg1, reg2)
+            | Instruction::AND(reg1, reg2)
+            | Instruction::OR(reg1, reg2)
+            | Instruction::XOR(reg1, reg2)
+            | Instruction::FMIN(reg1, reg2)
+            | Instruction::FMAX(reg1, reg2)
+            | Instruction::CMOVE(reg1, reg2)
+            | Instruction::CMOVNE(reg1, reg2)
+            | Instruction::CMOVG(reg1, reg2)
+            | Instruction::CMOVL(reg1, reg2)
+            | Instruction::CMOVGE(reg1, reg2)
+            | Instruction::CMOVLE(reg1, reg2)
+            | Instruction::CMOVA(reg1, reg2)
+            | Instruction::CMOVB(reg1, reg2)
+            | Instruction::CMOVU(reg1, reg2)
+            | Instruction::PUSHA(reg1, reg2)
+            | Instruction::POPA(reg1, reg2)
+            | Instruction::LOAD8U(reg1, reg2)
+            | Instruction::LOAD8S(reg1, reg2)
+            | Instruction::LOAD16U(reg1, reg2)
+            | Instruction::LOAD16S(reg1, reg2)
+            | Instruction::LOAD32U(reg1, reg2)
+            | Instruction::LOAD32S(reg1, reg2)
+            | Instruction::LOAD64U(reg1, reg2)
+            | Instruction::LOAD64S(reg1, reg2)
+            | Instruction::LOADF32(reg1, reg2)
+            | Instruction::LOADF64(reg1, reg2)
+            | Instruction::STORE8(reg1, reg2)
+            | Instruction::STORE16(reg1, reg2)
+            | Instruction::STORE32(reg1, reg2)
+            | Instruction::STORE64(reg1, reg2)
+            | Instruction::STOREF32(reg1, reg2)
+            | Instruction::STOREF64(reg1, reg2)
+            | Instruction::ALLOC(reg1, reg2)
+            | Instruction::REALLOC(reg1, reg2)
+            | Instruction::NEWOBJ(reg1, reg2)
+            | Instruction::GETFIELD(reg1, reg2, _)
+            | Instruction::SETFIELD(reg1, _, reg2)
+            | Instruction::STRLEN(reg1, reg2)
+            | Instruction::STRCMP(reg1, reg2)
+            | Instruction::TOSTR(reg1, reg2)
+            | Instruction::PARSEI(reg1, reg2)
+            | Instruction::PARSEF(reg1, reg2)
+            | Instruction::NEWARRAY(reg1, _, reg2)
+            | Instruction::ALEN(reg1, reg2)
+            | Instruction::AFILL(reg1, reg2)
+            | Instruction::LOADMOD(reg1, reg2)
+            | Instruction::LOADSYM(reg1, reg2) => vec![reg1, reg2],
+            Instruction::JMP(reg)
+            | Instruction::JE(reg)
+            | Instruction::JNE(reg)
+            | Instruction::PRINTR(reg)
+            | Instruction::FREE(reg)
+            | Instruction::LDSTR(reg, _)
+            | Instruction::PRINTS(reg)
+            | Instruction::VPUSHR(reg)
+            | Instruction::VPOP(reg)
+            | Instruction::CALL(reg)
+            | Instruction::JG(reg)
+            | Instruction::JL(reg)
+            | Instruction::SHR(reg, _)
+            | Instruction::SHL(reg, _)
+            | Instruction::FSQRT(reg)
+            | Instruction::FLOOR(reg)
+            | Instruction::CEIL(reg)
+            | Instruction::TRUNC(reg)
+            | Instruction::ROUND(reg)
+            | Instruction::JU(reg)
+            | Instruction::JGE(reg)
+            | Instruction::JLE(reg)
+            | Instruction::JA(reg)
+            | Instruction::JB(reg)
+            | Instruction::SETE(reg)
+            | Instruction::SETNE(reg)
+            | Instruction::SETG(reg)
+            | Instruction::SETL(reg)
+            | Instruction::SETGE(reg)
+            | Instruction::SETLE(reg)
+            | Instruction::SETA(reg)
+            | Instruction::SETB(reg)
+            | Instruction::SETU(reg)
+            | Instruction::ADDI(reg, _)
+            | Instruction::SUBI(reg, _)
+            | Instruction::MULI(reg, _)
+            | Instruction::DIVI(reg, _)
+            | Instruction::ANDI(reg, _)
+            | Instruction::ORI(reg, _)
+            | Instruction::XORI(reg, _)
+            | Instruction::SHRI(reg, _)
+            | Instruction::SHLI(reg, _)
+            | Instruction::LOADLOCAL(reg, _)
+            | Instruction::STORELOCAL(_, reg)
+            | Instruction::LOADARG(reg, _)
+            | Instruction::TAILCALL(reg, _)
+            | Instruction::SWITCH(reg, _) => vec![reg],
+            Instruction::FMA(reg1, reg2, reg3)
+            | Instruction::ADD3(reg1, reg2, reg3)
+            | Instruction::SUB3(reg1, reg2, reg3)
+            | Instruction::MUL3(reg1, reg2, reg3)
+            | Instruction::DIV3(reg1, reg2, reg3)
+            | Instruction::AND3(reg1, reg2, reg3)
+            | Instruction::OR3(reg1, reg2, reg3)
+            | Instruction::XOR3(reg1, reg2, reg3)
+            | Instruction::SHR3(reg1, reg2, reg3)
+            | Instruction::SHL3(reg1, reg2, reg3)
+            | Instruction::STRCAT(reg1, reg2, reg3)
+            | Instruction::CHARAT(reg1, reg2, reg3)
+            | Instruction::ALOAD(reg1, reg2, reg3)
+            | Instruction::ASTORE(reg1, reg2, reg3) => vec![reg1, reg2, reg3],
+            Instruction::SUBSTR(reg1, reg2, reg3, reg4) => vec![reg1, reg2, reg3, reg4],
+            Instruction::ACOPY(reg1, reg2, reg3, reg4, reg5) => vec![reg1, reg2, reg3, reg4, reg5],
+        }
+    }
+
+    //registers the instruction writes
+    pub fn destinations(&self) -> Vec<Register> {
+        match *self {
+            Instruction::POPA(reg1, reg2) => (reg1..=reg2).collect(),
+            Instruction::MOV(reg, _)
+            | Instruction::MOVR(reg, _)
+            | Instruction::CMOVE(reg, _)
+            | Instruction::CMOVNE(reg, _)
+            | Instruction::CMOVG(reg, _)
+            | Instruction::CMOVL(reg, _)
+            | Instruction::CMOVGE(reg, _)
+            | Instruction::CMOVLE(reg, _)
+            | Instruction::CMOVA(reg, _)
+            | Instruction::CMOVB(reg, _)
+            | Instruction::CMOVU(reg, _)
+            | Instruction::SETE(reg)
+            | Instruction::SETNE(reg)
+            | Instruction::SETG(reg)
+            | Instruction::SETL(reg)
+            | Instruction::SETGE(reg)
+            | Instruction::SETLE(reg)
+            | Instruction::SETA(reg)
+            | Instruction::SETB(reg)
+            | Instruction::SETU(reg)
+            | Instruction::ADD3(reg, _, _)
+            | Instruction::SUB3(reg, _, _)
+            | Instruction::MUL3(reg, _, _)
+            | Instruction::DIV3(reg, _, _)
+            | Instruction::AND3(reg, _, _)
+            | Instruction::OR3(reg, _, _)
+            | Instruction::XOR3(reg, _, _)
+            | Instruction::SHR3(reg, _, _)
+            | Instruction::SHL3(reg, _, _)
+            | Instruction::ADDI(reg, _)
+            | Instruction::SUBI(reg, _)
+            | Instruction::MULI(reg, _)
+            | Instruction::DIVI(reg, _)
+            | Instruction::ANDI(reg, _)
+            | Instruction::ORI(reg, _)
+            | Instruction::XORI(reg, _)
+            | Instruction::SHRI(reg, _)
+            | Instruction::SHLI(reg, _)
+            | Instruction::VLOADR(reg, _)
+            | Instruction::VPOP(reg)
+            | Instruction::LOADLOCAL(reg, _)
+            | Instruction::LOADARG(reg, _)
+            | Instruction::LOAD8U(reg, _)
+            | Instruction::LOAD8S(reg, _)
+            | Instruction::LOAD16U(reg, _)
+            | Instruction::LOAD16S(reg, _)
+            | Instruction::LOAD32U(reg, _)
+            | Instruction::LOAD32S(reg, _)
+            | Instruction::LOAD64U(reg, _)
+            | Instruction::LOAD64S(reg, _)
+            | Instruction::LOADF32(reg, _)
+            | Instruction::LOADF64(reg, _)
+            | Instruction::ALLOC(reg, _)
+            | Instruction::REALLOC(reg, _)
+            | Instruction::NEWOBJ(reg, _)
+            | Instruction::GETFIELD(reg, _, _)
+            | Instruction::LDSTR(reg, _)
+            | Instruction::STRCAT(reg, _, _)
+            | Instruction::STRLEN(reg, _)
+            | Instruction::SUBSTR(reg, _, _, _)
+            | Instruction::CHARAT(reg, _, _)
+            | Instruction::TOSTR(reg, _)
+            | Instruction::PARSEI(reg, _)
+            | Instruction::PARSEF(reg, _)
+            | Instruction::NEWARRAY(reg, _, _)
+            | Instruction::ALOAD(reg, _, _)
+            | Instruction::ALEN(reg, _)
+            | Instruction::LOADMOD(reg, _)
+            | Instruction::LOADSYM(reg, _) => vec![reg],
+            _ => Vec::new()
+        }
+    }
+}
+
+//decode_immediate that returns None instead of reading past the end of input
+pub(crate) fn try_decode_immediate(input: &[u8], ip: &mut usize) -> Option<Immediate> {
+    let size = match *input.get(*ip + 1)? {
+        0 | 1 | 12 => 1,
+        2 | 3 => 2,
+        4 | 5 | 8 | 13 => 4,
+        6 | 7 | 9 => 8,
+        14 | 15 => 16,
+        _ => 0
+    };
+    if input.len() < *ip + 2 + size {
+        return None;
+    }
+    Some(decode_immediate(input, ip))
 }
 
-fn decode_immediate(input: &Vec<u8>, ip: &mut usize) -> Immediate {
+//tags 10 and 11 are reserved since heap references cannot be encoded; like any unknown tag they decode to None()
+pub(crate) fn decode_immediate(input: &[u8], ip: &mut usize) -> Immediate {
     *ip += 1;
     match input[*ip] {
         0 => {
             *ip += 1;
-            Immediate::U8(input[*ip] as u8)
+            Immediate::U8(input[*ip])
         },
         1 => {
             *ip += 1;
@@ -121,14 +634,136 @@ fn decode_immediate(input: &Vec<u8>, ip: &mut usize) -> Immediate {
             *ip += size - 1;
             Immediate::F64(value)
         },
+        12 => {
+            *ip += 1;
+            Immediate::Bool(input[*ip] != 0)
+        },
+        13 => {
+            *ip += 1;
+            let size = mem::size_of::<u32>();
+            let value = u32::from_le_bytes(input[*ip..][..size].try_into().unwrap());
+            *ip += size - 1;
+            char::from_u32(value).map_or(Immediate::None(), Immediate::Char)
+        },
+        14 => {
+            *ip += 1;
+            let size = mem::size_of::<u128>();
+            let value = u128::from_le_bytes(input[*ip..][..size].try_into().unwrap());
+            *ip += size - 1;
+            Immediate::U128(value)
+        },
+        15 => {
+            *ip += 1;
+            let size = mem::size_of::<i128>();
+            let value = i128::from_le_bytes(input[*ip..][..size].try_into().unwrap());
+            *ip += size - 1;
+            Immediate::I128(value)
+        },
         _ => Immediate::None()
     }
 }
 
+//mode byte: 0..=2 absolute 8/16/32-bit, 3..=5 relative 8/16/32-bit
+fn decode_target(input: &[u8], ip: &mut usize) -> Target {
+    *ip += 1;
+    let mode = input[*ip];
+    *ip += 1;
+    match mode {
+        0 => Target::Absolute(input[*ip] as Address),
+        1 => {
+            let value = u16::from_le_bytes(input[*ip..][..2].try_into().unwrap());
+            *ip += 1;
+            Target::Absolute(value as Address)
+        },
+        2 => {
+            let value = u32::from_le_bytes(input[*ip..][..4].try_into().unwrap());
+            *ip += 3;
+            Target::Absolute(value as Address)
+        },
+        3 => Target::Relative(input[*ip] as i8 as isize),
+        4 => {
+            let value = i16::from_le_bytes(input[*ip..][..2].try_into().unwrap());
+            *ip += 1;
+            Target::Relative(value as isize)
+        },
+        _ => {
+            let value = i32::from_le_bytes(input[*ip..][..4].try_into().unwrap());
+            *ip += 3;
+            Target::Relative(value as isize)
+        }
+    }
+}
+
+//extended heap operand, mode byte: 0 direct 32-bit, 1 register, 2 base + 32-bit offset, 3 base + index * scale
+fn decode_heap_address(input: &[u8], ip: &mut usize) -> HeapAddress {
+    *ip += 1;
+    let mode = input[*ip];
+    *ip += 1;
+    match mode {
+        0 => {
+            let value = u32::from_le_bytes(input[*ip..][..4].try_into().unwrap());
+            *ip += 3;
+            HeapAddress::Direct(value as Address)
+        },
+        1 => HeapAddress::Indirect(input[*ip] as Register),
+        2 => {
+            let base = input[*ip] as Register;
+            *ip += 1;
+            let offset = i32::from_le_bytes(input[*ip..][..4].try_into().unwrap());
+            *ip += 3;
+            HeapAddress::Offset(base, offset as isize)
+        },
+        _ => {
+            let base = input[*ip] as Register;
+            *ip += 1;
+            let index = input[*ip] as Register;
+            *ip += 1;
+            let scale = input[*ip] as usize;
+            HeapAddress::Indexed(base, index, scale)
+        }
+    }
+}
+
+//decoded code together with its string constant pool and initialised heap slots
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub code: Vec<Instruction>,
+    pub strings: Vec<String>,
+    pub data: Vec<(Address, Immediate)>,
+}
+
 pub fn decode(input: Vec<u8>) -> Vec<Instruction> {
+    decode_program(input).code
+}
+
+//string pool entries (opcode 144, u32 length, UTF-8 bytes) and data entries (opcode 161, u32 slot,
+//immediate) may appear anywhere in the stream and do not occupy an instruction index
+pub fn decode_program(input: Vec<u8>) -> Program {
+    let len = input.len();
+    decode_until(input, len).0
+}
+
+//longest encoding of a single instruction or data entry
+const MAX_INSTRUCTION_LEN: usize = 32;
+
+//checked variant of decode_program for untrusted input: the stream is decoded from a zero padded
+//copy and None is returned if an instruction or pool entry runs past its end
+pub fn try_decode_program(input: &[u8]) -> Option<Program> {
+    let mut padded = input.to_vec();
+    padded.resize(input.len() + MAX_INSTRUCTION_LEN, 0);
+    match decode_until(padded, input.len()) {
+        (program, end) if end <= input.len() => Some(program),
+        _ => None
+    }
+}
+
+//decodes the instructions starting before `len`, returning the program and the position decoding stopped at
+fn decode_until(input: Vec<u8>, len: usize) -> (Program, usize) {
     let mut ip = 0usize;
     let mut ret = Vec::new();
-    while ip < input.len() {
+    let mut strings = Vec::new();
+    let mut data = Vec::new();
+    while ip < len {
         let ins = match input[ip] {
             0 => Instruction::NOP(),
             1 => {
@@ -173,18 +808,18 @@ pub fn decode(input: Vec<u8>) -> Vec<Instruction> {
             },
             8 => {
                 ip += 1;
-                let addr = input[ip] as Address;
+                let addr = HeapAddress::Direct(input[ip] as Address);
                 Instruction::PRINTV(addr)
             },
             9 => {
                 ip += 1;
-                let addr = input[ip] as Address;
+                let addr = HeapAddress::Direct(input[ip] as Address);
                 let var = decode_immediate(&input, &mut ip);
                 Instruction::VSTORE(addr, var)
             },
             10 => {
                 ip += 1;
-                let addr = input[ip] as Address;
+                let addr = HeapAddress::Direct(input[ip] as Address);
                 Instruction::VLOAD(addr)
             },
             11 => {
@@ -217,7 +852,7 @@ pub fn decode(input: Vec<u8>) -> Vec<Instruction> {
             },
             15 => {
                 ip += 1;
-                let addr = input[ip] as Address;
+                let addr = HeapAddress::Direct(input[ip] as Address);
                 ip += 1;
                 let reg = input[ip] as Register;
                 Instruction::VSTORER(addr, reg)
@@ -226,7 +861,7 @@ pub fn decode(input: Vec<u8>) -> Vec<Instruction> {
                 ip += 1;
                 let reg = input[ip] as Register;
                 ip += 1;
-                let addr = input[ip] as Address;
+                let addr = HeapAddress::Direct(input[ip] as Address);
                 Instruction::VLOADR(reg, addr)
             },
             17 => {
@@ -293,12 +928,1692 @@ pub fn decode(input: Vec<u8>) -> Vec<Instruction> {
                 let var = decode_immediate(&input, &mut ip);
                 Instruction::SHL(reg, var)
             },
-            _ => Instruction::NOP(),
-        };
-        ret.push(ins);
-        ip += 1;
-    }
-    ret
-}
-
-
+            30 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::FSQRT(reg)
+            },
+            31 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::FMIN(reg1, reg2)
+            },
+            32 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::FMAX(reg1, reg2)
+            },
+            33 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::FLOOR(reg)
+            },
+            34 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::CEIL(reg)
+            },
+            35 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::TRUNC(reg)
+            },
+            36 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::ROUND(reg)
+            },
+            37 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::FMA(reg1, reg2, reg3)
+            },
+            38 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::JU(reg)
+            },
+            39 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::JGE(reg)
+            },
+            40 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::JLE(reg)
+            },
+            41 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::JA(reg)
+            },
+            42 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::JB(reg)
+            },
+            43 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVE(reg1, reg2)
+            },
+            44 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVNE(reg1, reg2)
+            },
+            45 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVG(reg1, reg2)
+            },
+            46 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVL(reg1, reg2)
+            },
+            47 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVGE(reg1, reg2)
+            },
+            48 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVLE(reg1, reg2)
+            },
+            49 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVA(reg1, reg2)
+            },
+            50 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVB(reg1, reg2)
+            },
+            51 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::CMOVU(reg1, reg2)
+            },
+            52 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETE(reg)
+            },
+            53 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETNE(reg)
+            },
+            54 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETG(reg)
+            },
+            55 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETL(reg)
+            },
+            56 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETGE(reg)
+            },
+            57 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETLE(reg)
+            },
+            58 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETA(reg)
+            },
+            59 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETB(reg)
+            },
+            60 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::SETU(reg)
+            },
+            61 => Instruction::DUP(),
+            62 => Instruction::SWAP(),
+            63 => Instruction::OVER(),
+            64 => Instruction::ROT(),
+            65 => Instruction::DROP(),
+            66 => {
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::PICK(n)
+            },
+            67 => Instruction::SADD(),
+            68 => Instruction::SSUB(),
+            69 => Instruction::SMUL(),
+            70 => Instruction::SDIV(),
+            71 => Instruction::SCMP(),
+            72 => Instruction::SAND(),
+            73 => Instruction::SOR(),
+            74 => Instruction::SXOR(),
+            75 => Instruction::SSHR(),
+            76 => Instruction::SSHL(),
+            77 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::ADD3(reg1, reg2, reg3)
+            },
+            78 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::SUB3(reg1, reg2, reg3)
+            },
+            79 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::MUL3(reg1, reg2, reg3)
+            },
+            80 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::DIV3(reg1, reg2, reg3)
+            },
+            81 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::AND3(reg1, reg2, reg3)
+            },
+            82 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::OR3(reg1, reg2, reg3)
+            },
+            83 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::XOR3(reg1, reg2, reg3)
+            },
+            84 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::SHR3(reg1, reg2, reg3)
+            },
+            85 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::SHL3(reg1, reg2, reg3)
+            },
+            86 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::ADDI(reg, var)
+            },
+            87 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::SUBI(reg, var)
+            },
+            88 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::MULI(reg, var)
+            },
+            89 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::DIVI(reg, var)
+            },
+            90 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::ANDI(reg, var)
+            },
+            91 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::ORI(reg, var)
+            },
+            92 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::XORI(reg, var)
+            },
+            93 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::SHRI(reg, var)
+            },
+            94 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::SHLI(reg, var)
+            },
+            95 => {
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::ENTER(n)
+            },
+            96 => Instruction::LEAVE(),
+            97 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::LOADLOCAL(reg, n)
+            },
+            98 => {
+                ip += 1;
+                let n = input[ip] as usize;
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::STORELOCAL(n, reg)
+            },
+            99 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::LOADARG(reg, n)
+            },
+            100 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::PUSHA(reg1, reg2)
+            },
+            101 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::POPA(reg1, reg2)
+            },
+            102 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::TAILCALL(reg, n)
+            },
+            103 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JMPI(target)
+            },
+            104 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JEI(target)
+            },
+            105 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JNEI(target)
+            },
+            106 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JGI(target)
+            },
+            107 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JLI(target)
+            },
+            108 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JGEI(target)
+            },
+            109 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JLEI(target)
+            },
+            110 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JAI(target)
+            },
+            111 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JBI(target)
+            },
+            112 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::JUI(target)
+            },
+            113 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::CALLI(target)
+            },
+            114 => {
+                let target = decode_target(&input, &mut ip);
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::TAILCALLI(target, n)
+            },
+            115 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                ip += 1;
+                let n = u16::from_le_bytes(input[ip..][..2].try_into().unwrap()) as usize;
+                ip += 1;
+                Instruction::SWITCH(reg, n)
+            },
+            116 => {
+                let target = decode_target(&input, &mut ip);
+                Instruction::CASE(target)
+            },
+            117 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD8U(reg1, reg2)
+            },
+            118 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD8S(reg1, reg2)
+            },
+            119 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD16U(reg1, reg2)
+            },
+            120 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD16S(reg1, reg2)
+            },
+            121 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD32U(reg1, reg2)
+            },
+            122 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD32S(reg1, reg2)
+            },
+            123 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD64U(reg1, reg2)
+            },
+            124 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOAD64S(reg1, reg2)
+            },
+            125 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOADF32(reg1, reg2)
+            },
+            126 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOADF64(reg1, reg2)
+            },
+            127 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STORE8(reg1, reg2)
+            },
+            128 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STORE16(reg1, reg2)
+            },
+            129 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STORE32(reg1, reg2)
+            },
+            130 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STORE64(reg1, reg2)
+            },
+            131 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STOREF32(reg1, reg2)
+            },
+            132 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STOREF64(reg1, reg2)
+            },
+            133 => {
+                let addr = decode_heap_address(&input, &mut ip);
+                Instruction::PRINTV(addr)
+            },
+            134 => {
+                let addr = decode_heap_address(&input, &mut ip);
+                let var = decode_immediate(&input, &mut ip);
+                Instruction::VSTORE(addr, var)
+            },
+            135 => {
+                let addr = decode_heap_address(&input, &mut ip);
+                Instruction::VLOAD(addr)
+            },
+            136 => {
+                let addr = decode_heap_address(&input, &mut ip);
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::VSTORER(addr, reg)
+            },
+            137 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                let addr = decode_heap_address(&input, &mut ip);
+                Instruction::VLOADR(reg, addr)
+            },
+            138 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::ALLOC(reg1, reg2)
+            },
+            139 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::FREE(reg)
+            },
+            140 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::REALLOC(reg1, reg2)
+            },
+            141 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::NEWOBJ(reg1, reg2)
+            },
+            142 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let n = input[ip] as usize;
+                Instruction::GETFIELD(reg1, reg2, n)
+            },
+            143 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let n = input[ip] as usize;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::SETFIELD(reg1, n, reg2)
+            },
+            144 => {
+                ip += 1;
+                let size = u32::from_le_bytes(input[ip..][..4].try_into().unwrap()) as usize;
+                ip += 4;
+                match input.get(ip..ip + size) {
+                    Some(bytes) => strings.push(String::from_utf8_lossy(bytes).into_owned()),
+                    _ => return (Program { code: ret, strings, data }, usize::MAX)
+                }
+                ip += size;
+                continue;
+            },
+            145 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                ip += 1;
+                let n = u16::from_le_bytes(input[ip..][..2].try_into().unwrap()) as usize;
+                ip += 1;
+                Instruction::LDSTR(reg, n)
+            },
+            146 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::STRCAT(reg1, reg2, reg3)
+            },
+            147 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STRLEN(reg1, reg2)
+            },
+            148 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                ip += 1;
+                let reg4 = input[ip] as Register;
+                Instruction::SUBSTR(reg1, reg2, reg3, reg4)
+            },
+            149 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::STRCMP(reg1, reg2)
+            },
+            150 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::CHARAT(reg1, reg2, reg3)
+            },
+            151 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::TOSTR(reg1, reg2)
+            },
+            152 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::PARSEI(reg1, reg2)
+            },
+            153 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::PARSEF(reg1, reg2)
+            },
+            154 => {
+                ip += 1;
+                let reg = input[ip] as Register;
+                Instruction::PRINTS(reg)
+            },
+            155 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                let var = decode_immediate(&input, &mut ip);
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::NEWARRAY(reg1, var, reg2)
+            },
+            156 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::ALOAD(reg1, reg2, reg3)
+            },
+            157 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                Instruction::ASTORE(reg1, reg2, reg3)
+            },
+            158 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::ALEN(reg1, reg2)
+            },
+            159 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                ip += 1;
+                let reg3 = input[ip] as Register;
+                ip += 1;
+                let reg4 = input[ip] as Register;
+                ip += 1;
+                let reg5 = input[ip] as Register;
+                Instruction::ACOPY(reg1, reg2, reg3, reg4, reg5)
+            },
+            160 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::AFILL(reg1, reg2)
+            },
+            162 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOADMOD(reg1, reg2)
+            },
+            163 => {
+                ip += 1;
+                let reg1 = input[ip] as Register;
+                ip += 1;
+                let reg2 = input[ip] as Register;
+                Instruction::LOADSYM(reg1, reg2)
+            },
+            161 => {
+                ip += 1;
+                let addr = u32::from_le_bytes(input[ip..][..4].try_into().unwrap()) as Address;
+                ip += 3;
+                data.push((addr, decode_immediate(&input, &mut ip)));
+                ip += 1;
+                continue;
+            },
+            _ => Instruction::NOP(),
+        };
+        ret.push(ins);
+        ip += 1;
+    }
+    (Program { code: ret, strings, data }, ip)
+}
+
+pub(crate) fn encode_immediate(out: &mut Vec<u8>, var: Immediate) {
+    match var {
+        Immediate::U8(v) => {
+            out.push(0);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::I8(v) => {
+            out.push(1);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::U16(v) => {
+            out.push(2);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::I16(v) => {
+            out.push(3);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::U32(v) => {
+            out.push(4);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::I32(v) => {
+            out.push(5);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::U64(v) => {
+            out.push(6);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::I64(v) => {
+            out.push(7);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::F32(v) => {
+            out.push(8);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::F64(v) => {
+            out.push(9);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        //references only come from NEWOBJ, NEWARRAY and LDSTR; an encoded one could alias any object
+        Immediate::Ref(_) | Immediate::Str(_) => panic!("Cannot encode a heap reference: {:?}", var),
+        Immediate::Bool(v) => {
+            out.push(12);
+            out.push(v as u8);
+        },
+        Immediate::Char(v) => {
+            out.push(13);
+            out.extend_from_slice(&(v as u32).to_le_bytes());
+        },
+        Immediate::U128(v) => {
+            out.push(14);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::I128(v) => {
+            out.push(15);
+            out.extend_from_slice(&v.to_le_bytes());
+        },
+        Immediate::None() => out.push(0xFF),
+    }
+}
+
+fn encode_heap_address(out: &mut Vec<u8>, addr: HeapAddress) {
+    match addr {
+        HeapAddress::Direct(a) => {
+            out.push(0);
+            out.extend_from_slice(&(a as u32).to_le_bytes());
+        },
+        HeapAddress::Indirect(base) => {
+            out.push(1);
+            out.push(base as u8);
+        },
+        HeapAddress::Offset(base, offset) => {
+            out.push(2);
+            out.push(base as u8);
+            out.extend_from_slice(&(offset as i32).to_le_bytes());
+        },
+        HeapAddress::Indexed(base, index, scale) => {
+            out.push(3);
+            out.push(base as u8);
+            out.push(index as u8);
+            out.push(scale as u8);
+        }
+    }
+}
+
+//picks the narrowest displacement that holds the target
+fn encode_target(out: &mut Vec<u8>, target: Target) {
+    match target {
+        Target::Absolute(a) if a <= u8::MAX as Address => {
+            out.push(0);
+            out.push(a as u8);
+        },
+        Target::Absolute(a) if a <= u16::MAX as Address => {
+            out.push(1);
+            out.extend_from_slice(&(a as u16).to_le_bytes());
+        },
+        Target::Absolute(a) => {
+            out.push(2);
+            out.extend_from_slice(&(a as u32).to_le_bytes());
+        },
+        Target::Relative(d) if d >= i8::MIN as isize && d <= i8::MAX as isize => {
+            out.push(3);
+            out.push(d as i8 as u8);
+        },
+        Target::Relative(d) if d >= i16::MIN as isize && d <= i16::MAX as isize => {
+            out.push(4);
+            out.extend_from_slice(&(d as i16).to_le_bytes());
+        },
+        Target::Relative(d) => {
+            out.push(5);
+            out.extend_from_slice(&(d as i32).to_le_bytes());
+        }
+    }
+}
+
+pub fn encode(code: &[Instruction]) -> Vec<u8> {
+    encode_code(Vec::new(), code)
+}
+
+//emits the string pool and the data entries ahead of the code
+pub fn encode_program(program: &Program) -> Vec<u8> {
+    let mut out = Vec::new();
+    for string in &program.strings {
+        out.push(144);
+        out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+        out.extend_from_slice(string.as_bytes());
+    }
+    for &(addr, value) in &program.data {
+        out.push(161);
+        out.extend_from_slice(&(addr as u32).to_le_bytes());
+        encode_immediate(&mut out, value);
+    }
+    encode_code(out, &program.code)
+}
+
+fn encode_code(mut out: Vec<u8>, code: &[Instruction]) -> Vec<u8> {
+    for ins in code {
+        match *ins {
+            Instruction::NOP() => out.push(0),
+            Instruction::MOV(register, var) => {
+                out.push(1);
+                out.push(register as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::MOVR(reg1, reg2) => {
+                out.push(2);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::JMP(reg) => {
+                out.push(3);
+                out.push(reg as u8);
+            },
+            Instruction::JE(reg) => {
+                out.push(4);
+                out.push(reg as u8);
+            },
+            Instruction::JNE(reg) => {
+                out.push(5);
+                out.push(reg as u8);
+            },
+            Instruction::CMP(reg1, reg2) => {
+                out.push(6);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::PRINTR(reg) => {
+                out.push(7);
+                out.push(reg as u8);
+            },
+            Instruction::PRINTV(HeapAddress::Direct(addr)) if addr <= u8::MAX as Address => {
+                out.push(8);
+                out.push(addr as u8);
+            },
+            Instruction::PRINTV(addr) => {
+                out.push(133);
+                encode_heap_address(&mut out, addr);
+            },
+            Instruction::VSTORE(HeapAddress::Direct(addr), var) if addr <= u8::MAX as Address => {
+                out.push(9);
+                out.push(addr as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::VSTORE(addr, var) => {
+                out.push(134);
+                encode_heap_address(&mut out, addr);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::VLOAD(HeapAddress::Direct(addr)) if addr <= u8::MAX as Address => {
+                out.push(10);
+                out.push(addr as u8);
+            },
+            Instruction::VLOAD(addr) => {
+                out.push(135);
+                encode_heap_address(&mut out, addr);
+            },
+            Instruction::ADD(reg1, reg2) => {
+                out.push(11);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::SUB(reg1, reg2) => {
+                out.push(12);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::MUL(reg1, reg2) => {
+                out.push(13);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::DIV(reg1, reg2) => {
+                out.push(14);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::VSTORER(HeapAddress::Direct(addr), reg) if addr <= u8::MAX as Address => {
+                out.push(15);
+                out.push(addr as u8);
+                out.push(reg as u8);
+            },
+            Instruction::VSTORER(addr, reg) => {
+                out.push(136);
+                encode_heap_address(&mut out, addr);
+                out.push(reg as u8);
+            },
+            Instruction::VLOADR(reg, HeapAddress::Direct(addr)) if addr <= u8::MAX as Address => {
+                out.push(16);
+                out.push(reg as u8);
+                out.push(addr as u8);
+            },
+            Instruction::VLOADR(reg, addr) => {
+                out.push(137);
+                out.push(reg as u8);
+                encode_heap_address(&mut out, addr);
+            },
+            Instruction::VPUSH(var) => {
+                out.push(17);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::VPUSHR(reg) => {
+                out.push(18);
+                out.push(reg as u8);
+            },
+            Instruction::VPOP(reg) => {
+                out.push(19);
+                out.push(reg as u8);
+            },
+            Instruction::CALL(reg) => {
+                out.push(20);
+                out.push(reg as u8);
+            },
+            Instruction::RET() => out.push(21),
+            Instruction::HALT() => out.push(22),
+            Instruction::JG(reg) => {
+                out.push(23);
+                out.push(reg as u8);
+            },
+            Instruction::JL(reg) => {
+                out.push(24);
+                out.push(reg as u8);
+            },
+            Instruction::AND(reg1, reg2) => {
+                out.push(25);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::OR(reg1, reg2) => {
+                out.push(26);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::XOR(reg1, reg2) => {
+                out.push(27);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::SHR(reg, var) => {
+                out.push(28);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::SHL(reg, var) => {
+                out.push(29);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::FSQRT(reg) => {
+                out.push(30);
+                out.push(reg as u8);
+            },
+            Instruction::FMIN(reg1, reg2) => {
+                out.push(31);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::FMAX(reg1, reg2) => {
+                out.push(32);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::FLOOR(reg) => {
+                out.push(33);
+                out.push(reg as u8);
+            },
+            Instruction::CEIL(reg) => {
+                out.push(34);
+                out.push(reg as u8);
+            },
+            Instruction::TRUNC(reg) => {
+                out.push(35);
+                out.push(reg as u8);
+            },
+            Instruction::ROUND(reg) => {
+                out.push(36);
+                out.push(reg as u8);
+            },
+            Instruction::FMA(reg1, reg2, reg3) => {
+                out.push(37);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::JU(reg) => {
+                out.push(38);
+                out.push(reg as u8);
+            },
+            Instruction::JGE(reg) => {
+                out.push(39);
+                out.push(reg as u8);
+            },
+            Instruction::JLE(reg) => {
+                out.push(40);
+                out.push(reg as u8);
+            },
+            Instruction::JA(reg) => {
+                out.push(41);
+                out.push(reg as u8);
+            },
+            Instruction::JB(reg) => {
+                out.push(42);
+                out.push(reg as u8);
+            },
+            Instruction::CMOVE(reg1, reg2) => {
+                out.push(43);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVNE(reg1, reg2) => {
+                out.push(44);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVG(reg1, reg2) => {
+                out.push(45);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVL(reg1, reg2) => {
+                out.push(46);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVGE(reg1, reg2) => {
+                out.push(47);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVLE(reg1, reg2) => {
+                out.push(48);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVA(reg1, reg2) => {
+                out.push(49);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVB(reg1, reg2) => {
+                out.push(50);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CMOVU(reg1, reg2) => {
+                out.push(51);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::SETE(reg) => {
+                out.push(52);
+                out.push(reg as u8);
+            },
+            Instruction::SETNE(reg) => {
+                out.push(53);
+                out.push(reg as u8);
+            },
+            Instruction::SETG(reg) => {
+                out.push(54);
+                out.push(reg as u8);
+            },
+            Instruction::SETL(reg) => {
+                out.push(55);
+                out.push(reg as u8);
+            },
+            Instruction::SETGE(reg) => {
+                out.push(56);
+                out.push(reg as u8);
+            },
+            Instruction::SETLE(reg) => {
+                out.push(57);
+                out.push(reg as u8);
+            },
+            Instruction::SETA(reg) => {
+                out.push(58);
+                out.push(reg as u8);
+            },
+            Instruction::SETB(reg) => {
+                out.push(59);
+                out.push(reg as u8);
+            },
+            Instruction::SETU(reg) => {
+                out.push(60);
+                out.push(reg as u8);
+            },
+            Instruction::DUP() => out.push(61),
+            Instruction::SWAP() => out.push(62),
+            Instruction::OVER() => out.push(63),
+            Instruction::ROT() => out.push(64),
+            Instruction::DROP() => out.push(65),
+            Instruction::PICK(n) => {
+                out.push(66);
+                out.push(n as u8);
+            },
+            Instruction::SADD() => out.push(67),
+            Instruction::SSUB() => out.push(68),
+            Instruction::SMUL() => out.push(69),
+            Instruction::SDIV() => out.push(70),
+            Instruction::SCMP() => out.push(71),
+            Instruction::SAND() => out.push(72),
+            Instruction::SOR() => out.push(73),
+            Instruction::SXOR() => out.push(74),
+            Instruction::SSHR() => out.push(75),
+            Instruction::SSHL() => out.push(76),
+            Instruction::ADD3(reg1, reg2, reg3) => {
+                out.push(77);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::SUB3(reg1, reg2, reg3) => {
+                out.push(78);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::MUL3(reg1, reg2, reg3) => {
+                out.push(79);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::DIV3(reg1, reg2, reg3) => {
+                out.push(80);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::AND3(reg1, reg2, reg3) => {
+                out.push(81);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::OR3(reg1, reg2, reg3) => {
+                out.push(82);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::XOR3(reg1, reg2, reg3) => {
+                out.push(83);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::SHR3(reg1, reg2, reg3) => {
+                out.push(84);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::SHL3(reg1, reg2, reg3) => {
+                out.push(85);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::ADDI(reg, var) => {
+                out.push(86);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::SUBI(reg, var) => {
+                out.push(87);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::MULI(reg, var) => {
+                out.push(88);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::DIVI(reg, var) => {
+                out.push(89);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::ANDI(reg, var) => {
+                out.push(90);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::ORI(reg, var) => {
+                out.push(91);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::XORI(reg, var) => {
+                out.push(92);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::SHRI(reg, var) => {
+                out.push(93);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::SHLI(reg, var) => {
+                out.push(94);
+                out.push(reg as u8);
+                encode_immediate(&mut out, var);
+            },
+            Instruction::ENTER(n) => {
+                out.push(95);
+                out.push(n as u8);
+            },
+            Instruction::LEAVE() => out.push(96),
+            Instruction::LOADLOCAL(reg, n) => {
+                out.push(97);
+                out.push(reg as u8);
+                out.push(n as u8);
+            },
+            Instruction::STORELOCAL(n, reg) => {
+                out.push(98);
+                out.push(n as u8);
+                out.push(reg as u8);
+            },
+            Instruction::LOADARG(reg, n) => {
+                out.push(99);
+                out.push(reg as u8);
+                out.push(n as u8);
+            },
+            Instruction::PUSHA(reg1, reg2) => {
+                out.push(100);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::POPA(reg1, reg2) => {
+                out.push(101);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::TAILCALL(reg, n) => {
+                out.push(102);
+                out.push(reg as u8);
+                out.push(n as u8);
+            },
+            Instruction::JMPI(target) => {
+                out.push(103);
+                encode_target(&mut out, target);
+            },
+            Instruction::JEI(target) => {
+                out.push(104);
+                encode_target(&mut out, target);
+            },
+            Instruction::JNEI(target) => {
+                out.push(105);
+                encode_target(&mut out, target);
+            },
+            Instruction::JGI(target) => {
+                out.push(106);
+                encode_target(&mut out, target);
+            },
+            Instruction::JLI(target) => {
+                out.push(107);
+                encode_target(&mut out, target);
+            },
+            Instruction::JGEI(target) => {
+                out.push(108);
+                encode_target(&mut out, target);
+            },
+            Instruction::JLEI(target) => {
+                out.push(109);
+                encode_target(&mut out, target);
+            },
+            Instruction::JAI(target) => {
+                out.push(110);
+                encode_target(&mut out, target);
+            },
+            Instruction::JBI(target) => {
+                out.push(111);
+                encode_target(&mut out, target);
+            },
+            Instruction::JUI(target) => {
+                out.push(112);
+                encode_target(&mut out, target);
+            },
+            Instruction::CALLI(target) => {
+                out.push(113);
+                encode_target(&mut out, target);
+            },
+            Instruction::TAILCALLI(target, n) => {
+                out.push(114);
+                encode_target(&mut out, target);
+                out.push(n as u8);
+            },
+            Instruction::SWITCH(reg, n) => {
+                out.push(115);
+                out.push(reg as u8);
+                let n = u16::try_from(n).unwrap_or_else(|_| panic!("Cannot encode a SWITCH table of {} entries", n));
+                out.extend_from_slice(&n.to_le_bytes());
+            },
+            Instruction::CASE(target) => {
+                out.push(116);
+                encode_target(&mut out, target);
+            },
+            Instruction::LOAD8U(reg1, reg2) => {
+                out.push(117);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD8S(reg1, reg2) => {
+                out.push(118);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD16U(reg1, reg2) => {
+                out.push(119);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD16S(reg1, reg2) => {
+                out.push(120);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD32U(reg1, reg2) => {
+                out.push(121);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD32S(reg1, reg2) => {
+                out.push(122);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD64U(reg1, reg2) => {
+                out.push(123);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOAD64S(reg1, reg2) => {
+                out.push(124);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOADF32(reg1, reg2) => {
+                out.push(125);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOADF64(reg1, reg2) => {
+                out.push(126);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STORE8(reg1, reg2) => {
+                out.push(127);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STORE16(reg1, reg2) => {
+                out.push(128);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STORE32(reg1, reg2) => {
+                out.push(129);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STORE64(reg1, reg2) => {
+                out.push(130);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STOREF32(reg1, reg2) => {
+                out.push(131);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::STOREF64(reg1, reg2) => {
+                out.push(132);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::ALLOC(reg1, reg2) => {
+                out.push(138);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::FREE(reg) => {
+                out.push(139);
+                out.push(reg as u8);
+            },
+            Instruction::REALLOC(reg1, reg2) => {
+                out.push(140);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::NEWOBJ(reg1, reg2) => {
+                out.push(141);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::GETFIELD(reg1, reg2, n) => {
+                out.push(142);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(n as u8);
+            },
+            Instruction::SETFIELD(reg1, n, reg2) => {
+                out.push(143);
+                out.push(reg1 as u8);
+                out.push(n as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LDSTR(reg, n) => {
+                out.push(145);
+                out.push(reg as u8);
+                let n = u16::try_from(n).unwrap_or_else(|_| panic!("Cannot encode string pool index {}", n));
+                out.extend_from_slice(&n.to_le_bytes());
+            },
+            Instruction::STRCAT(reg1, reg2, reg3) => {
+                out.push(146);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::STRLEN(reg1, reg2) => {
+                out.push(147);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::SUBSTR(reg1, reg2, reg3, reg4) => {
+                out.push(148);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+                out.push(reg4 as u8);
+            },
+            Instruction::STRCMP(reg1, reg2) => {
+                out.push(149);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::CHARAT(reg1, reg2, reg3) => {
+                out.push(150);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::TOSTR(reg1, reg2) => {
+                out.push(151);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::PARSEI(reg1, reg2) => {
+                out.push(152);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::PARSEF(reg1, reg2) => {
+                out.push(153);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::PRINTS(reg) => {
+                out.push(154);
+                out.push(reg as u8);
+            },
+            Instruction::NEWARRAY(reg1, var, reg2) => {
+                out.push(155);
+                out.push(reg1 as u8);
+                encode_immediate(&mut out, var);
+                out.push(reg2 as u8);
+            },
+            Instruction::ALOAD(reg1, reg2, reg3) => {
+                out.push(156);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::ASTORE(reg1, reg2, reg3) => {
+                out.push(157);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+            },
+            Instruction::ALEN(reg1, reg2) => {
+                out.push(158);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::ACOPY(reg1, reg2, reg3, reg4, reg5) => {
+                out.push(159);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+                out.push(reg3 as u8);
+                out.push(reg4 as u8);
+                out.push(reg5 as u8);
+            },
+            Instruction::AFILL(reg1, reg2) => {
+                out.push(160);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOADMOD(reg1, reg2) => {
+                out.push(162);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+            Instruction::LOADSYM(reg1, reg2) => {
+                out.push(163);
+                out.push(reg1 as u8);
+                out.push(reg2 as u8);
+            },
+
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn immediates_round_trip() {
+        let values = [
+            Immediate::None(),
+            Immediate::U8(u8::MAX),
+            Immediate::I8(i8::MIN),
+            Immediate::U16(u16::MAX),
+            Immediate::I16(i16::MIN),
+            Immediate::U32(u32::MAX),
+            Immediate::I32(i32::MIN),
+            Immediate::U64(u64::MAX),
+            Immediate::I64(i64::MIN),
+            Immediate::U128(u128::MAX),
+            Immediate::I128(i128::MIN),
+            Immediate::F32(-1.5),
+            Immediate::F64(f64::MAX),
+            Immediate::Bool(true),
+            Immediate::Char('\u{1F600}'),
+        ];
+        for &v in &values {
+            let code = vec![Instruction::MOV(3, v), Instruction::ADDI(7, v), Instruction::VPUSH(v)];
+            assert_eq!(decode(encode(&code)), code);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot encode a SWITCH table of 65536 entries")]
+    fn oversized_switch_tables_are_rejected() {
+        encode(&[Instruction::SWITCH(0, 65536)]);
+    }
+}
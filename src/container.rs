@@ -0,0 +1,260 @@
+use crate::bytecode::*;
+use crate::debug::*;
+use std::convert::TryInto;
+
+//container layout, all integers little-endian:
+//  magic, u16 version, u16 flags, u32 entry point, u16 register count, u16 section count,
+//  each section as a u8 kind, u32 payload length and the payload,
+//  then a u32 FNV-1a checksum of every preceding byte
+pub const MAGIC: [u8; 4] = *b"SVMC";
+pub const VERSION: u16 = 1;
+
+const SECTION_CODE: u8 = 1;         //encoded instructions
+const SECTION_CONSTANTS: u8 = 2;    //string constant pool: u32 count, then u32 length and UTF-8 bytes each
+const SECTION_DATA: u8 = 3;         //heap initialisers: u32 count, then u32 slot and an encoded immediate each
+const SECTION_SYMBOLS: u8 = 4;      //u32 count, then u32 instruction index, u16 length and UTF-8 name each
+const SECTION_DEBUG: u8 = 5;        //u16 file count, then u16 length and name each; u32 line count, then u32 instruction
+                                    //index, u16 file, u32 line and u32 column each; u32 symbol count, then u8 kind,
+                                    //u32 address, u16 length and UTF-8 name each
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ContainerError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    BadChecksum,
+    Truncated,
+    InvalidString,
+    InvalidSymbolKind(u8),
+    MissingCode,
+    InvalidRegisterCount(u16),
+    DataOutOfRange(Address),    //a data segment slot beyond the heap capacity of the VM loading the program
+}
+
+#[derive(Debug, Clone)]
+pub struct Container {
+    pub flags: u16,
+    pub entry: Address,                     //instruction index execution starts at
+    pub registers: usize,                   //size of the register file the program was built for, 1..=256
+    pub program: Program,
+    pub symbols: Vec<(String, Address)>,
+    pub debug: Option<DebugInfo>,
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Container { flags: 0, entry: 0, registers: 8, program: Program::default(), symbols: Vec::new(), debug: None }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+fn push_section(out: &mut Vec<u8>, kind: u8, payload: &[u8]) {
+    out.push(kind);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+pub fn write(container: &Container) -> Vec<u8> {
+    let mut sections = vec![(SECTION_CODE, encode(&container.program.code))];
+    if !container.program.strings.is_empty() {
+        let mut payload = (container.program.strings.len() as u32).to_le_bytes().to_vec();
+        for string in &container.program.strings {
+            payload.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            payload.extend_from_slice(string.as_bytes());
+        }
+        sections.push((SECTION_CONSTANTS, payload));
+    }
+    if !container.program.data.is_empty() {
+        let mut payload = (container.program.data.len() as u32).to_le_bytes().to_vec();
+        for &(addr, value) in &container.program.data {
+            payload.extend_from_slice(&(addr as u32).to_le_bytes());
+            encode_immediate(&mut payload, value);
+        }
+        sections.push((SECTION_DATA, payload));
+    }
+    if !container.symbols.is_empty() {
+        let mut payload = (container.symbols.len() as u32).to_le_bytes().to_vec();
+        for (name, addr) in &container.symbols {
+            payload.extend_from_slice(&(*addr as u32).to_le_bytes());
+            payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            payload.extend_from_slice(name.as_bytes());
+        }
+        sections.push((SECTION_SYMBOLS, payload));
+    }
+    if let Some(debug) = &container.debug {
+        sections.push((SECTION_DEBUG, write_debug(debug)));
+    }
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&container.flags.to_le_bytes());
+    out.extend_from_slice(&(container.entry as u32).to_le_bytes());
+    out.extend_from_slice(&(container.registers as u16).to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    for (kind, payload) in &sections {
+        push_section(&mut out, *kind, payload);
+    }
+    let sum = checksum(&out);
+    out.extend_from_slice(&sum.to_le_bytes());
+    out
+}
+
+fn write_debug(debug: &DebugInfo) -> Vec<u8> {
+    let mut payload = (debug.files.len() as u16).to_le_bytes().to_vec();
+    for file in &debug.files {
+        payload.extend_from_slice(&(file.len() as u16).to_le_bytes());
+        payload.extend_from_slice(file.as_bytes());
+    }
+    payload.extend_from_slice(&(debug.lines.len() as u32).to_le_bytes());
+    for (addr, loc) in &debug.lines {
+        payload.extend_from_slice(&(*addr as u32).to_le_bytes());
+        payload.extend_from_slice(&(loc.file as u16).to_le_bytes());
+        payload.extend_from_slice(&loc.line.to_le_bytes());
+        payload.extend_from_slice(&loc.column.to_le_bytes());
+    }
+    payload.extend_from_slice(&(debug.symbols.len() as u32).to_le_bytes());
+    for (name, kind, addr) in &debug.symbols {
+        payload.push(match kind {
+            SymbolKind::Label => 0,
+            SymbolKind::Function => 1,
+            SymbolKind::Heap => 2,
+        });
+        payload.extend_from_slice(&(*addr as u32).to_le_bytes());
+        payload.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        payload.extend_from_slice(name.as_bytes());
+    }
+    payload
+}
+
+fn read_debug(s: &mut Reader) -> Result<DebugInfo, ContainerError> {
+    let mut debug = DebugInfo::default();
+    for _ in 0..s.u16()? {
+        let len = s.u16()? as usize;
+        debug.files.push(s.string(len)?);
+    }
+    for _ in 0..s.u32()? {
+        let addr = s.u32()? as Address;
+        let loc = SourceLocation { file: s.u16()? as usize, line: s.u32()?, column: s.u32()? };
+        debug.lines.push((addr, loc));
+    }
+    //DebugInfo::location searches the line table by instruction index
+    debug.lines.sort_by_key(|&(addr, _)| addr);
+    for _ in 0..s.u32()? {
+        let kind = match s.u8()? {
+            0 => SymbolKind::Label,
+            1 => SymbolKind::Function,
+            2 => SymbolKind::Heap,
+            k => return Err(ContainerError::InvalidSymbolKind(k)),
+        };
+        let addr = s.u32()? as Address;
+        let len = s.u16()? as usize;
+        debug.symbols.push((s.string(len)?, kind, addr));
+    }
+    Ok(debug)
+}
+
+//bounds-checked cursor over a byte slice
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ContainerError> {
+        if self.input.len() - self.pos < len {
+            return Err(ContainerError::Truncated);
+        }
+        self.pos += len;
+        Ok(&self.input[self.pos - len..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8, ContainerError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ContainerError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ContainerError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, ContainerError> {
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| ContainerError::InvalidString)
+    }
+
+    //decoding expects the position of the byte before the tag, which the slot read beforehand guarantees
+    fn immediate(&mut self) -> Result<Immediate, ContainerError> {
+        if self.pos == 0 {
+            return Err(ContainerError::Truncated);
+        }
+        let mut ip = self.pos - 1;
+        let value = try_decode_immediate(self.input, &mut ip).ok_or(ContainerError::Truncated)?;
+        self.pos = ip + 1;
+        Ok(value)
+    }
+}
+
+//unknown section kinds are skipped so that newer optional sections do not break older readers
+pub fn read(input: &[u8]) -> Result<Container, ContainerError> {
+    if input.len() < MAGIC.len() || input[..MAGIC.len()] != MAGIC {
+        return Err(ContainerError::BadMagic);
+    }
+    if input.len() < MAGIC.len() + 4 {
+        return Err(ContainerError::Truncated);
+    }
+    let (body, sum) = input.split_at(input.len() - 4);
+    let mut r = Reader { input: body, pos: MAGIC.len() };
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(ContainerError::UnsupportedVersion(version));
+    }
+    if checksum(body) != u32::from_le_bytes(sum.try_into().unwrap()) {
+        return Err(ContainerError::BadChecksum);
+    }
+    let mut container = Container { flags: r.u16()?, entry: r.u32()? as Address, ..Container::default() };
+    container.registers = match r.u16()? {
+        n @ 1..=256 => n as usize,
+        n => return Err(ContainerError::InvalidRegisterCount(n))
+    };
+    let mut has_code = false;
+    for _ in 0..r.u16()? {
+        let kind = r.u8()?;
+        let len = r.u32()? as usize;
+        let mut s = Reader { input: r.bytes(len)?, pos: 0 };
+        match kind {
+            SECTION_CODE => {
+                container.program.code = try_decode_program(s.input).ok_or(ContainerError::Truncated)?.code;
+                has_code = true;
+            },
+            SECTION_CONSTANTS => {
+                for _ in 0..s.u32()? {
+                    let len = s.u32()? as usize;
+                    container.program.strings.push(s.string(len)?);
+                }
+            },
+            SECTION_DATA => {
+                for _ in 0..s.u32()? {
+                    let addr = s.u32()? as Address;
+                    container.program.data.push((addr, s.immediate()?));
+                }
+            },
+            SECTION_SYMBOLS => {
+                for _ in 0..s.u32()? {
+                    let addr = s.u32()? as Address;
+                    let len = s.u16()? as usize;
+                    container.symbols.push((s.string(len)?, addr));
+                }
+            },
+            SECTION_DEBUG => container.debug = Some(read_debug(&mut s)?),
+            _ => {}
+        }
+    }
+    if !has_code {
+        return Err(ContainerError::MissingCode);
+    }
+    Ok(container)
+}
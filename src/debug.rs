@@ -0,0 +1,58 @@
+use crate::bytecode::Address;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file: usize,    //index into DebugInfo::files
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SymbolKind {
+    Label,      //instruction index
+    Function,   //instruction index of a function entry
+    Heap,       //typed heap slot
+}
+
+//maps instruction indices back to source positions and names
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<(Address, SourceLocation)>,          //sorted by instruction index, each entry holds until the next
+    pub symbols: Vec<(String, SymbolKind, Address)>,
+}
+
+impl DebugInfo {
+    pub fn location(&self, ip: Address) -> Option<SourceLocation> {
+        let i = self.lines.partition_point(|&(start, _)| start <= ip);
+        if i == 0 {
+            return None;
+        }
+        Some(self.lines[i - 1].1)
+    }
+
+    pub fn symbol(&self, kind: SymbolKind, addr: Address) -> Option<&str> {
+        self.symbols.iter().find(|&&(_, k, a)| k == kind && a == addr).map(|(name, _, _)| name.as_str())
+    }
+
+    //the function whose entry is closest before ip, with the distance from it
+    pub fn function(&self, ip: Address) -> Option<(&str, usize)> {
+        self.symbols.iter()
+            .filter(|&&(_, kind, addr)| kind == SymbolKind::Function && addr <= ip)
+            .max_by_key(|&&(_, _, addr)| addr)
+            .map(|(name, _, addr)| (name.as_str(), ip - addr))
+    }
+
+    //"name+offset at file:line:column", leaving out the parts that are not known
+    pub fn describe(&self, ip: Address) -> String {
+        let mut parts = Vec::new();
+        if let Some((name, offset)) = self.function(ip) {
+            parts.push(if offset == 0 { name.to_string() } else { format!("{}+{}", name, offset) });
+        }
+        if let Some(loc) = self.location(ip) {
+            let file = self.files.get(loc.file).map_or("?", |f| f.as_str());
+            parts.push(format!("at {}:{}:{}", file, loc.line, loc.column));
+        }
+        parts.join(" ")
+    }
+}
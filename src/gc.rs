@@ -0,0 +1,124 @@
+use crate::bytecode::Immediate;
+
+//counters exposed to the host through VirtualMachine::gc_stats
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated_objects: usize,
+    pub freed_objects: usize,
+    pub live_objects: usize,
+    pub live_fields: usize,
+}
+
+//largest field count NEWOBJ and NEWARRAY accept; larger requests fault the instruction.
+//the total stays bounded because the field count of the live objects also triggers collections
+pub const MAX_OBJECT_FIELDS: usize = 1 << 24;
+
+pub enum Object {
+    Record(Vec<Immediate>),
+    Array(Immediate, Vec<Immediate>),   //element type given by the variant of the fill value, None() for references
+    Str(String),
+}
+
+impl Object {
+    pub fn fields(&self) -> &[Immediate] {
+        match self {
+            Object::Record(fields) | Object::Array(_, fields) => fields,
+            Object::Str(_) => &[]
+        }
+    }
+}
+
+//the object index held by a reference value
+fn reference(v: &Immediate) -> Option<usize> {
+    match *v {
+        Immediate::Ref(r) | Immediate::Str(r) => Some(r),
+        _ => None
+    }
+}
+
+//managed heap of objects addressed by Immediate::Ref, reclaimed by mark and sweep
+pub struct ObjectHeap {
+    objects: Vec<Option<Object>>,
+    free: Vec<usize>,
+    threshold: usize,          //live objects that trigger a collection
+    field_threshold: usize,    //live fields that trigger a collection
+    stats: GcStats,
+}
+
+impl ObjectHeap {
+    pub fn new() -> Self {
+        ObjectHeap { objects: Vec::new(), free: Vec::new(), threshold: 1024, field_threshold: 1 << 20, stats: GcStats::default() }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    //true once enough objects or fields are live that allocating `fields` more should collect first
+    pub fn should_collect(&self, fields: usize) -> bool {
+        self.stats.live_objects >= self.threshold || self.stats.live_fields + fields > self.field_threshold
+    }
+
+    pub fn alloc(&mut self, object: Object) -> usize {
+        self.stats.allocated_objects += 1;
+        self.stats.live_objects += 1;
+        self.stats.live_fields += object.fields().len();
+        match self.free.pop() {
+            Some(i) => {
+                self.objects[i] = Some(object);
+                i
+            },
+            _ => {
+                self.objects.push(Some(object));
+                self.objects.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, r: usize) -> Option<&Object> {
+        self.objects.get(r)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, r: usize) -> Option<&mut Object> {
+        self.objects.get_mut(r)?.as_mut()
+    }
+
+    //marks everything reachable from `roots` and frees the rest, returning the number freed
+    pub fn collect<'a, I: Iterator<Item = &'a Immediate>>(&mut self, roots: I) -> usize {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<usize> = roots.filter_map(reference).collect();
+        while let Some(r) = pending.pop() {
+            if r >= marked.len() || marked[r] {
+                continue;
+            }
+            if let Some(object) = &self.objects[r] {
+                marked[r] = true;
+                pending.extend(object.fields().iter().filter_map(reference));
+            }
+        }
+        let mut freed = 0;
+        for (i, slot) in self.objects.iter_mut().enumerate() {
+            if !marked[i] {
+                if let Some(object) = slot.take() {
+                    self.stats.live_fields -= object.fields().len();
+                    self.free.push(i);
+                    freed += 1;
+                }
+            }
+        }
+        self.stats.collections += 1;
+        self.stats.freed_objects += freed;
+        self.stats.live_objects -= freed;
+        //grow the threshold so collections stay proportional to the live set
+        self.threshold = self.threshold.max(self.stats.live_objects * 2);
+        self.field_threshold = self.field_threshold.max(self.stats.live_fields * 2);
+        freed
+    }
+}
+
+impl Default for ObjectHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,9 @@
+#![allow(clippy::upper_case_acronyms)]
+pub mod vm;
+pub mod bytecode;
+pub mod verifier;
+pub mod allocator;
+pub mod gc;
+pub mod container;
+pub mod debug;
+pub mod linker;
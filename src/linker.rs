@@ -0,0 +1,176 @@
+use crate::bytecode::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+//what an address operand points into
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Section {
+    Code,   //instruction index
+    Heap,   //typed heap slot
+}
+
+//an address operand that depends on where the module ends up
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub index: Address,             //instruction holding the operand
+    pub section: Section,
+    pub symbol: Option<String>,     //imported symbol added to the operand, or None to add the module's own base
+}
+
+//separately assembled unit of code: addresses are local to the module and relocations say which ones to adjust.
+//the symbols named by its relocations are its imports
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub program: Program,
+    pub heap_size: usize,                           //static heap slots reserved, grown to cover the data segment
+    pub exports: Vec<(String, Section, Address)>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    WrongSection(String),       //a code relocation resolved to a heap symbol or the reverse
+    BadRelocation(Address),     //the instruction has no absolute address operand of that section
+    StringOutOfRange(Address),  //the LDSTR at this index no longer fits its 16-bit pool index once rebased
+}
+
+impl Module {
+    //number of heap slots the module occupies once linked
+    pub fn heap_extent(&self) -> usize {
+        self.program.data.iter().map(|&(addr, _)| addr + 1).fold(self.heap_size, usize::max)
+    }
+}
+
+//the same variant holding a new value, if it fits
+fn rebase_immediate(v: Immediate, addr: Address) -> Option<Immediate> {
+    match v {
+        Immediate::U8(_) => u8::try_from(addr).ok().map(Immediate::U8),
+        Immediate::I8(_) => i8::try_from(addr).ok().map(Immediate::I8),
+        Immediate::U16(_) => u16::try_from(addr).ok().map(Immediate::U16),
+        Immediate::I16(_) => i16::try_from(addr).ok().map(Immediate::I16),
+        Immediate::U32(_) => u32::try_from(addr).ok().map(Immediate::U32),
+        Immediate::I32(_) => i32::try_from(addr).ok().map(Immediate::I32),
+        Immediate::U64(_) => Some(Immediate::U64(addr as u64)),
+        Immediate::I64(_) => i64::try_from(addr).ok().map(Immediate::I64),
+        Immediate::U128(_) => Some(Immediate::U128(addr as u128)),
+        Immediate::I128(_) => Some(Immediate::I128(addr as i128)),
+        _ => None
+    }
+}
+
+fn rebase_target<F: Fn(Address) -> Address>(target: Target, f: F) -> Option<Target> {
+    match target {
+        Target::Absolute(a) => Some(Target::Absolute(f(a))),
+        Target::Relative(_) => None
+    }
+}
+
+fn rebase_heap<F: Fn(Address) -> Address>(addr: HeapAddress, f: F) -> Option<HeapAddress> {
+    match addr {
+        HeapAddress::Direct(a) => Some(HeapAddress::Direct(f(a))),
+        _ => None
+    }
+}
+
+//applies f to the absolute address operand of ins that points into `section`.
+//MOV immediates are patched for either section since registers hold both kinds of address
+fn patch<F: Fn(Address) -> Address>(ins: Instruction, section: Section, f: F) -> Option<Instruction> {
+    match (ins, section) {
+        (Instruction::MOV(reg, v), _) => {
+            let a = Address::try_from(v.as_i128()?).ok()?;
+            rebase_immediate(v, f(a)).map(|v| Instruction::MOV(reg, v))
+        },
+        (Instruction::JMPI(t), Section::Code) => rebase_target(t, f).map(Instruction::JMPI),
+        (Instruction::JEI(t), Section::Code) => rebase_target(t, f).map(Instruction::JEI),
+        (Instruction::JNEI(t), Section::Code) => rebase_target(t, f).map(Instruction::JNEI),
+        (Instruction::JGI(t), Section::Code) => rebase_target(t, f).map(Instruction::JGI),
+        (Instruction::JLI(t), Section::Code) => rebase_target(t, f).map(Instruction::JLI),
+        (Instruction::JGEI(t), Section::Code) => rebase_target(t, f).map(Instruction::JGEI),
+        (Instruction::JLEI(t), Section::Code) => rebase_target(t, f).map(Instruction::JLEI),
+        (Instruction::JAI(t), Section::Code) => rebase_target(t, f).map(Instruction::JAI),
+        (Instruction::JBI(t), Section::Code) => rebase_target(t, f).map(Instruction::JBI),
+        (Instruction::JUI(t), Section::Code) => rebase_target(t, f).map(Instruction::JUI),
+        (Instruction::CALLI(t), Section::Code) => rebase_target(t, f).map(Instruction::CALLI),
+        (Instruction::TAILCALLI(t, n), Section::Code) => rebase_target(t, f).map(|t| Instruction::TAILCALLI(t, n)),
+        (Instruction::CASE(t), Section::Code) => rebase_target(t, f).map(Instruction::CASE),
+        (Instruction::PRINTV(a), Section::Heap) => rebase_heap(a, f).map(Instruction::PRINTV),
+        (Instruction::VSTORE(a, v), Section::Heap) => rebase_heap(a, f).map(|a| Instruction::VSTORE(a, v)),
+        (Instruction::VLOAD(a), Section::Heap) => rebase_heap(a, f).map(Instruction::VLOAD),
+        (Instruction::VSTORER(a, reg), Section::Heap) => rebase_heap(a, f).map(|a| Instruction::VSTORER(a, reg)),
+        (Instruction::VLOADR(reg, a), Section::Heap) => rebase_heap(a, f).map(|a| Instruction::VLOADR(reg, a)),
+        _ => None
+    }
+}
+
+//moves a module to the given code, heap and string pool bases, resolving its imports through `resolve`.
+//string pool indices and the data segment are rebased without needing relocations
+pub fn relocate<R>(module: &Module, code_base: Address, heap_base: Address, string_base: usize, resolve: R) -> Result<Program, LinkError>
+    where R: Fn(&str) -> Option<(Section, Address)>
+{
+    let mut code: Vec<Instruction> = module.program.code.iter().enumerate().map(|(i, &ins)| match ins {
+        Instruction::LDSTR(reg, n) => match n.checked_add(string_base) {
+            Some(n) if n <= u16::MAX as usize => Ok(Instruction::LDSTR(reg, n)),
+            _ => Err(LinkError::StringOutOfRange(i))
+        },
+        ins => Ok(ins)
+    }).collect::<Result<_, _>>()?;
+    for rel in &module.relocations {
+        let base = match &rel.symbol {
+            Some(name) => match resolve(name) {
+                Some((section, addr)) if section == rel.section => addr,
+                Some(_) => return Err(LinkError::WrongSection(name.clone())),
+                _ => return Err(LinkError::UndefinedSymbol(name.clone()))
+            },
+            _ => match rel.section {
+                Section::Code => code_base,
+                Section::Heap => heap_base
+            }
+        };
+        let ins = code.get(rel.index).ok_or(LinkError::BadRelocation(rel.index))?;
+        code[rel.index] = patch(*ins, rel.section, |a| a + base).ok_or(LinkError::BadRelocation(rel.index))?;
+    }
+    let data = module.program.data.iter().map(|&(addr, v)| (addr + heap_base, v)).collect();
+    Ok(Program { code, strings: module.program.strings.clone(), data })
+}
+
+//lays the modules out one after another and resolves every import against the exports of all of them.
+//the result stays relocatable: every operand that was relocated now holds an address inside the linked
+//module, so each relocation is kept as one against the linked module's own base
+pub fn link(modules: &[Module]) -> Result<Module, LinkError> {
+    let mut bases = Vec::new();
+    let (mut code_base, mut heap_base, mut string_base) = (0, 0, 0);
+    for module in modules {
+        bases.push((code_base, heap_base, string_base));
+        code_base += module.program.code.len();
+        heap_base += module.heap_extent();
+        string_base += module.program.strings.len();
+    }
+
+    let mut symbols = HashMap::new();
+    let mut exports = Vec::new();
+    for (module, &(code_base, heap_base, _)) in modules.iter().zip(&bases) {
+        for (name, section, addr) in &module.exports {
+            let addr = addr + match section {
+                Section::Code => code_base,
+                Section::Heap => heap_base
+            };
+            if symbols.insert(name.clone(), (*section, addr)).is_some() {
+                return Err(LinkError::DuplicateSymbol(name.clone()));
+            }
+            exports.push((name.clone(), *section, addr));
+        }
+    }
+
+    let mut program = Program::default();
+    let mut relocations = Vec::new();
+    for (module, &(code_base, heap_base, string_base)) in modules.iter().zip(&bases) {
+        let linked = relocate(module, code_base, heap_base, string_base, |name| symbols.get(name).copied())?;
+        program.code.extend(linked.code);
+        program.strings.extend(linked.strings);
+        program.data.extend(linked.data);
+        relocations.extend(module.relocations.iter().map(|rel| Relocation { index: rel.index + code_base, section: rel.section, symbol: None }));
+    }
+    Ok(Module { program, heap_size: heap_base, exports, relocations })
+}
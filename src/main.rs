@@ -1,9 +1,16 @@
-mod vm;
-mod bytecode;
+use simplevm::{vm, container};
+use std::{env, fs};
 
 fn main() {
+    //runs a container file when one is given, the built-in demo otherwise
+    if let Some(path) = env::args().nth(1) {
+        let bytes = fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        let program = container::read(&bytes).unwrap_or_else(|e| panic!("Invalid container {}: {:?}", path, e));
+        let mut test = vm::VirtualMachine::from_container(program, 1024).unwrap_or_else(|e| panic!("Cannot load container {}: {:?}", path, e));
+        test.cpu();
+        return;
+    }
     let code = vec![1, 1, 3, 100, 0 ,1, 2, 3, 0xFE, 0xFF, 6, 0, 1, 2, 2, 7, 0, 22,];
-    let mut test = vm::VirtualMachine::new(code, 1024);
+    let mut test = vm::VirtualMachine::new(code, 1024).unwrap();
     test.cpu();
 }
-
@@ -0,0 +1,69 @@
+use crate::bytecode::*;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VerifyError {
+    InvalidRegister,    //a register operand is outside the register file
+    ShortSwitchTable,   //fewer than n + 1 CASE entries follow a SWITCH
+    StrayCase,          //a CASE outside every SWITCH table, reachable by falling through into it
+}
+
+//checks every register operand against `register_count` and the layout of SWITCH tables,
+//returning the index of the first offending instruction
+pub fn verify(code: &[Instruction], register_count: usize) -> Result<(), (usize, VerifyError)> {
+    let mut table_end = 0;  //CASE entries before this index belong to a SWITCH table
+    for (i, ins) in code.iter().enumerate() {
+        if ins.registers().iter().any(|&r| r >= register_count) {
+            return Err((i, VerifyError::InvalidRegister));
+        }
+        match *ins {
+            Instruction::SWITCH(_, n) => {
+                //the default entry followed by entries 0..n
+                let table = n.checked_add(1).and_then(|len| code[i + 1..].get(..len));
+                match table {
+                    Some(entries) if entries.iter().all(|e| matches!(e, Instruction::CASE(_))) => table_end = i + 1 + entries.len(),
+                    _ => return Err((i, VerifyError::ShortSwitchTable))
+                }
+            },
+            Instruction::CASE(_) if i >= table_end => return Err((i, VerifyError::StrayCase)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+//instructions of called routines that overwrite a callee-saved register the routine has not saved with PUSHA.
+//routines are found from the immediate targets of CALLI and TAILCALLI and scanned in order up to their RET;
+//calls through a register cannot be followed statically
+pub fn callee_saved_violations(code: &[Instruction], callee_saved: &[Register]) -> Vec<(usize, Register)> {
+    let mut entries = BTreeSet::new();
+    for (i, ins) in code.iter().enumerate() {
+        let target = match *ins {
+            Instruction::CALLI(target) | Instruction::TAILCALLI(target, _) => target,
+            _ => continue
+        };
+        match target {
+            Target::Absolute(a) => entries.insert(a),
+            Target::Relative(offset) => entries.insert((i as isize + 1 + offset) as usize),
+        };
+    }
+    let mut violations = BTreeSet::new();
+    for entry in entries {
+        let mut saved = Vec::new();
+        for (i, ins) in code.iter().enumerate().skip(entry) {
+            match *ins {
+                Instruction::RET() | Instruction::HALT() => break,
+                Instruction::PUSHA(reg1, reg2) => saved.extend(reg1..=reg2),
+                Instruction::POPA(_, _) => {},
+                _ => {
+                    for r in ins.destinations() {
+                        if callee_saved.contains(&r) && !saved.contains(&r) {
+                            violations.insert((i, r));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    violations.into_iter().collect()
+}
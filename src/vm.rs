@@ -1,399 +1,1525 @@
 use crate::bytecode::*;
+use crate::verifier::{verify, callee_saved_violations};
+use crate::allocator::*;
+use crate::gc::*;
+use crate::container::{Container, ContainerError};
+use crate::debug::*;
+use crate::linker::{Module, Section, relocate};
+use std::cmp::Ordering;
+use std::mem;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Write};
 //this vm is based on tarekwiz's smallvm
+
+//calling convention:
+//  the caller pushes arguments on the stack and CALLs; argument 0 is the last one pushed.
+//  CALL opens a frame recording the return address and the current stack height (frame pointer).
+//  the callee reserves locals with ENTER n and reads arguments with LOADARG.
+//  results are returned in register 0. RET drops anything the callee left above the
+//  frame pointer and resumes the caller, which then pops its own arguments.
+//  stack instructions in the callee cannot pop below the frame pointer.
+struct Frame {
+    return_address: Address,
+    frame_pointer: usize,
+    locals: Vec<Immediate>,
+    saved: Vec<(Register, Immediate)>,
+}
+
+//how CALL and RET treat the callee-saved registers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CalleeSaved {
+    Ignore,     //callee-saved registers are not tracked
+    Preserve,   //saved on CALL and restored on RET
+    Check,      //saved on CALL and a warning printed on RET if the callee changed them
+}
+
 pub struct VirtualMachine {
     ip : Address,
     flag_eq : bool,
     flag_gt: bool,
-    reg : [Immediate; 8],
+    flag_lt: bool,
+    flag_above: bool,
+    flag_below: bool,
+    flag_uo: bool,
+    reg : Vec<Immediate>,
     code : Vec<Instruction>,
+    strings : Vec<String>,
     stack : Vec<Immediate>,
+    frames : Vec<Frame>,
+    callee_saved : Vec<Register>,
+    callee_saved_mode : CalleeSaved,
+    max_frames : usize,
     data : Vec<Immediate>,
+    memory : Vec<u8>,
+    allocator : Allocator,
+    objects : ObjectHeap,
+    output : Box<dyn Write>,
+    debug : Option<DebugInfo>,
+    symbols : HashMap<String, (Section, Address)>,
+    modules : HashMap<String, Module>,
+    loaded : HashSet<String>,
     is_executing : bool,
 }
 
 impl VirtualMachine {
    
-    pub fn new(c : Vec<u8>, heap_capacity: usize) -> Self {
-        let code = decode(c);
-        VirtualMachine { ip: 0, flag_eq: false, flag_gt: false, reg: [Immediate::U8(0); 8], code: code, stack: Vec::new(), data: vec![Immediate::U8(0); heap_capacity], is_executing: false } 
+    pub fn new(c : Vec<u8>, heap_capacity: usize) -> Result<Self, ContainerError> {
+        Self::with_registers(c, heap_capacity, 8)
+    }
+
+    //register operands are encoded in one byte, so at most 256 registers are addressable
+    pub fn with_registers(c : Vec<u8>, heap_capacity: usize, register_count: usize) -> Result<Self, ContainerError> {
+        Self::from_program(decode_program(c), heap_capacity, register_count)
+    }
+
+    //fails if the data segment initialises a slot beyond the heap capacity
+    pub fn from_program(program: Program, heap_capacity: usize, register_count: usize) -> Result<Self, ContainerError> {
+        assert!((1..=256).contains(&register_count), "register count must be between 1 and 256");
+        if let Some(&(addr, _)) = program.data.iter().find(|&&(addr, _)| addr >= heap_capacity) {
+            return Err(ContainerError::DataOutOfRange(addr));
+        }
+        let Program { code, strings, data: initial } = program;
+        //the data segment is loaded before execution and ALLOC hands out slots above it
+        let mut data = vec![Immediate::U8(0); heap_capacity];
+        let mut base = 0;
+        for (addr, value) in initial {
+            data[addr] = value;
+            base = base.max(addr + 1);
+        }
+        Ok(VirtualMachine { ip: 0, flag_eq: false, flag_gt: false, flag_lt: false, flag_above: false, flag_below: false, flag_uo: false, reg: vec![Immediate::U8(0); register_count], code, strings, stack: Vec::new(), frames: Vec::new(), callee_saved: Vec::new(), callee_saved_mode: CalleeSaved::Ignore, max_frames: usize::MAX, data, memory: vec![0; 65536], allocator: Allocator::new(base, heap_capacity, false), objects: ObjectHeap::new(), output: Box::new(io::stdout()), debug: None, symbols: HashMap::new(), modules: HashMap::new(), loaded: HashSet::new(), is_executing: false })
+    }
+
+    //loads the program of a container and starts execution at its entry point
+    pub fn from_container(container: Container, heap_capacity: usize) -> Result<Self, ContainerError> {
+        let mut vm = Self::from_program(container.program, heap_capacity, container.registers)?;
+        vm.ip = container.entry;
+        vm.debug = container.debug;
+        for (name, addr) in container.symbols {
+            vm.symbols.insert(name, (Section::Code, addr));
+        }
+        Ok(vm)
+    }
+
+    pub fn set_callee_saved(&mut self, regs: &[Register], mode: CalleeSaved) {
+        self.callee_saved = regs.to_vec();
+        self.callee_saved_mode = mode;
+    }
+
+    //limits the depth of the call stack; CALL fails once it is reached
+    pub fn set_stack_limit(&mut self, max_frames: usize) {
+        self.max_frames = max_frames;
+    }
+
+    //resizes the byte-addressed linear memory, zero filling any new bytes
+    pub fn set_memory_size(&mut self, size: usize) {
+        self.memory.resize(size, 0);
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    //ALLOC hands out heap slots from `base` upwards; slots below it stay free for static data.
+    //debug mode faults on double free and on accesses to freed blocks
+    pub fn configure_allocator(&mut self, base: Address, debug: bool) {
+        self.allocator = Allocator::new(base, self.data.len(), debug);
+    }
+
+    pub fn alloc_stats(&self) -> AllocStats {
+        self.allocator.stats()
+    }
+
+    //collects every object not reachable from the registers, the stack, the call frames or the heap
+    pub fn collect_garbage(&mut self) -> usize {
+        let locals = self.frames.iter().flat_map(|frame| frame.locals.iter());
+        let saved = self.frames.iter().flat_map(|frame| frame.saved.iter().map(|(_, v)| v));
+        let roots = self.reg.iter().chain(self.stack.iter()).chain(locals).chain(saved).chain(self.data.iter());
+        self.objects.collect(roots)
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.objects.stats()
+    }
+
+    //names a code label or heap slot of the running program for modules to import and LOADSYM to find
+    pub fn export(&mut self, name: &str, section: Section, addr: Address) {
+        self.symbols.insert(name.to_string(), (section, addr));
+    }
+
+    //makes a module available to LOADMOD under the given name
+    pub fn register_module(&mut self, name: &str, module: Module) {
+        self.modules.insert(name.to_string(), module);
+    }
+
+    //appends a registered module to the running program: its code and strings go after the existing ones,
+    //its static heap is taken from the allocator, its imports resolve against the exports loaded so far
+    //and its own exports are added to them. loading a module again does nothing
+    pub fn load_module(&mut self, name: &str) -> bool {
+        if self.loaded.contains(name) {
+            return true;
+        }
+        let module = match self.modules.get(name) {
+            Some(module) => module,
+            _ => {
+                println!("Error: module {} is not registered", name);
+                return false;
+            }
+        };
+        for (i, (export, _, _)) in module.exports.iter().enumerate() {
+            if self.symbols.contains_key(export) || module.exports[..i].iter().any(|(e, _, _)| e == export) {
+                println!("Error: module {} exports {} which is already defined", name, export);
+                return false;
+            }
+        }
+        let extent = module.heap_extent();
+        let heap_base = if extent == 0 { 0 } else {
+            match self.allocator.alloc(extent) {
+                Some(a) => a,
+                _ => {
+                    println!("Error: no heap space for the {} static slots of module {}", extent, name);
+                    return false;
+                }
+            }
+        };
+        let code_base = self.code.len();
+        let symbols = &self.symbols;
+        let program = match relocate(module, code_base, heap_base, self.strings.len(), |s| symbols.get(s).copied()) {
+            Ok(program) => program,
+            Err(e) => {
+                println!("Error: failed to link module {}: {:?}", name, e);
+                if extent > 0 {
+                    self.allocator.free(heap_base);
+                }
+                return false;
+            }
+        };
+        if let Err((i, e)) = verify(&program.code, self.reg.len()) {
+            println!("Error: module {} failed verification at instruction #{}: {:?} in {:?}", name, i, e, program.code[i]);
+            if extent > 0 {
+                self.allocator.free(heap_base);
+            }
+            return false;
+        }
+        for (export, section, addr) in &module.exports {
+            let base = match section {
+                Section::Code => code_base,
+                Section::Heap => heap_base
+            };
+            self.symbols.insert(export.clone(), (*section, addr + base));
+        }
+        for slot in &mut self.data[heap_base..heap_base + extent] {
+            *slot = Immediate::U8(0);
+        }
+        for (addr, value) in program.data {
+            self.data[addr] = value;
+        }
+        self.code.extend(program.code);
+        self.strings.extend(program.strings);
+        self.loaded.insert(name.to_string());
+        true
+    }
+
+    //source positions and names used by the tracer and in error messages
+    pub fn set_debug_info(&mut self, mut debug: DebugInfo) {
+        debug.lines.sort_by_key(|&(addr, _)| addr);
+        self.debug = Some(debug);
+    }
+
+    //" (function+offset at file:line:column)" for instruction i, empty without debug info
+    fn describe(&self, i: Address) -> String {
+        match self.debug.as_ref().map(|debug| debug.describe(i)) {
+            Some(text) if !text.is_empty() => format!(" ({})", text),
+            _ => String::new()
+        }
+    }
+
+    //" (name)" for a named heap slot, empty otherwise
+    fn heap_name(&self, slot: Address) -> String {
+        match self.debug.as_ref().and_then(|debug| debug.symbol(SymbolKind::Heap, slot)) {
+            Some(name) => format!(" ({})", name),
+            _ => String::new()
+        }
+    }
+
+    //destination of PRINTR, PRINTV and PRINTS, standard output by default
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    fn string(&self, reg: Register) -> Option<&str> {
+        match self.reg[reg] {
+            Immediate::Str(r) => match self.objects.get(r)? {
+                Object::Str(s) => Some(s),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    fn new_string(&mut self, s: String) -> Immediate {
+        if self.objects.should_collect(0) {
+            self.collect_garbage();
+        }
+        Immediate::Str(self.objects.alloc(Object::Str(s)))
+    }
+
+    fn object_ref(&self, reg: Register) -> Option<usize> {
+        match self.reg[reg] {
+            Immediate::Ref(r) => Some(r),
+            _ => None
+        }
+    }
+
+    fn array(&self, reg: Register) -> Option<(Immediate, &Vec<Immediate>)> {
+        match self.objects.get(self.object_ref(reg)?)? {
+            Object::Array(elem, items) => Some((*elem, items)),
+            _ => None
+        }
+    }
+
+    fn array_mut(&mut self, reg: Register) -> Option<(Immediate, &mut Vec<Immediate>)> {
+        let r = self.object_ref(reg)?;
+        match self.objects.get_mut(r)? {
+            Object::Array(elem, items) => Some((*elem, items)),
+            _ => None
+        }
+    }
+
+    //whether v may be stored in an array of element type elem. arrays filled with None() hold
+    //references, since Ref and Str values cannot be encoded as a fill immediate
+    fn element_fits(elem: Immediate, v: Immediate) -> bool {
+        match elem {
+            Immediate::None() => matches!(v, Immediate::None() | Immediate::Ref(_) | Immediate::Str(_)),
+            _ => mem::discriminant(&elem) == mem::discriminant(&v)
+        }
+    }
+
+    //checks index..index + count against the array length, reporting a fault when out of range
+    fn array_bounds(index: Address, count: usize, len: usize) -> bool {
+        match index.checked_add(count) {
+            Some(end) if end <= len => true,
+            _ => {
+                println!("Error: array index {} out of bounds for length {}", index, len);
+                false
+            }
+        }
+    }
+
+    fn reg_address(&self, reg: Register) -> Option<Address> {
+        self.reg[reg].as_i128().and_then(|a| Address::try_from(a).ok())
+    }
+
+    //resolves a heap operand to a slot index, None if it falls outside the heap
+    fn heap_slot(&self, addr: HeapAddress) -> Option<Address> {
+        let slot = match addr {
+            HeapAddress::Direct(a) => a as i128,
+            HeapAddress::Indirect(base) => self.reg[base].as_i128()?,
+            HeapAddress::Offset(base, offset) => self.reg[base].as_i128()?.checked_add(offset as i128)?,
+            HeapAddress::Indexed(base, index, scale) => {
+                let offset = self.reg[index].as_i128()?.checked_mul(scale as i128)?;
+                self.reg[base].as_i128()?.checked_add(offset)?
+            },
+        };
+        if slot < 0 || slot >= self.data.len() as i128 {
+            return None;
+        }
+        if self.allocator.is_poisoned(slot as Address) {
+            println!("Error: use after free of heap address {}{}", slot, self.heap_name(slot as Address));
+            return None;
+        }
+        Some(slot as Address)
+    }
+
+    //bounds-checked view of `width` bytes of linear memory at the address held in `reg`
+    fn memory_range(&self, reg: Register, width: usize) -> Option<std::ops::Range<usize>> {
+        let start = usize::try_from(self.reg[reg].as_i128()?).ok()?;
+        let end = start.checked_add(width)?;
+        if end > self.memory.len() {
+            return None;
+        }
+        Some(start..end)
+    }
+
+    fn load(&mut self, reg: Register, addr: Register, width: usize, make: fn(&[u8]) -> Immediate) -> bool {
+        match self.memory_range(addr, width) {
+            Some(range) => {
+                self.reg[reg] = make(&self.memory[range]);
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn store(&mut self, addr: Register, bytes: &[u8]) -> bool {
+        match self.memory_range(addr, bytes.len()) {
+            Some(range) => {
+                self.memory[range].copy_from_slice(bytes);
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn compare(&mut self, v1: Immediate, v2: Immediate) {
+        self.set_flags(v1.cmp_signed(v2), v1.cmp_unsigned(v2));
+    }
+
+    fn set_flags(&mut self, signed: Option<Ordering>, unsigned: Option<Ordering>) {
+        self.flag_eq = signed == Some(Ordering::Equal);
+        self.flag_gt = signed == Some(Ordering::Greater);
+        self.flag_lt = signed == Some(Ordering::Less);
+        self.flag_above = unsigned == Some(Ordering::Greater);
+        self.flag_below = unsigned == Some(Ordering::Less);
+        self.flag_uo = signed.is_none();
+    }
+
+    fn push_result(&mut self, result: Option<Immediate>) -> bool {
+        match result {
+            Some(r) => {
+                self.stack.push(r);
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn set_result(&mut self, reg: Register, result: Option<Immediate>) -> bool {
+        match result {
+            Some(r) => {
+                self.reg[reg] = r;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn jump(&mut self, target: Target) -> bool {
+        match target {
+            Target::Absolute(a) => {
+                self.ip = a;
+                true
+            },
+            Target::Relative(d) => {
+                match (self.ip as isize).checked_add(d) {
+                    Some(a) if a >= 0 => {
+                        self.ip = a as Address;
+                        true
+                    },
+                    _ => false
+                }
+            }
+        }
+    }
+
+    fn push_frame(&mut self) -> bool {
+        if self.frames.len() >= self.max_frames {
+            return false;
+        }
+        let saved = match self.callee_saved_mode {
+            CalleeSaved::Ignore => Vec::new(),
+            _ => self.callee_saved.iter().map(|&r| (r, self.reg[r])).collect()
+        };
+        self.frames.push(Frame { return_address: self.ip, frame_pointer: self.stack.len(), locals: Vec::new(), saved });
+        true
+    }
+
+    fn reuse_frame(&mut self, n: usize) -> bool {
+        let fp = match self.frames.last_mut() {
+            Some(frame) => {
+                frame.locals.clear();
+                frame.frame_pointer
+            },
+            _ => return false
+        };
+        let len = self.stack.len();
+        if n > fp || len < fp + n {
+            return false;
+        }
+        //the new arguments take the place of the current ones so the caller's cleanup still balances
+        self.stack.copy_within(len - n.., fp - n);
+        self.stack.truncate(fp);
+        true
+    }
+
+    //number of stack items above the current frame pointer; a callee may not pop its caller's items
+    fn stack_depth(&self) -> usize {
+        self.stack.len() - self.frames.last().map_or(0, |frame| frame.frame_pointer)
+    }
+
+    fn pop(&mut self) -> Option<Immediate> {
+        if self.stack_depth() == 0 {
+            return None;
+        }
+        self.stack.pop()
+    }
+
+    //pops the top two stack items, returning them in push order
+    fn pop_operands(&mut self) -> Option<(Immediate, Immediate)> {
+        let v2 = self.pop()?;
+        let v1 = self.pop()?;
+        Some((v1, v2))
     }
 
     fn execute(&mut self, instr: Instruction) -> bool
     {
-        println!("Executing: {:?} \t  current ip: {:?}", instr, self.ip + 1);
+        println!("Executing: {:?} \t  current ip: {:?}{}", instr, self.ip, self.describe(self.ip.saturating_sub(1)));
         match instr {
             Instruction::NOP() => true,
             Instruction::MOV(reg, var) => {
                 self.reg[reg] = var;
                 true
             },
-            Instruction::MOVR(reg1, reg2) => {
-                self.reg[reg1] = self.reg[reg2];
-                true
+            Instruction::MOVR(reg1, reg2) => {
+                self.reg[reg1] = self.reg[reg2];
+                true
+            },
+            Instruction::JMP(reg) => {
+                match self.reg[reg] {
+                    Immediate::U8(v) => self.jump(Target::Absolute(v as Address)),
+                    Immediate::U16(v) => self.jump(Target::Absolute(v as Address)),
+                    Immediate::U32(v) => self.jump(Target::Absolute(v as Address)),
+                    Immediate::U64(v) => self.jump(Target::Absolute(v as Address)),
+                    _ => false
+                }
+            },
+            Instruction::JMPI(target) => self.jump(target),
+            Instruction::JE(reg) => {
+                if !self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JNE(reg) => {
+                if self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JG(reg) => {
+                if !self.flag_gt {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JL(reg) => {
+                if !self.flag_lt {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JGE(reg) => {
+                if !(self.flag_gt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JLE(reg) => {
+                if !(self.flag_lt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JA(reg) => {
+                if !self.flag_above {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JB(reg) => {
+                if !self.flag_below {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JU(reg) => {
+                if !self.flag_uo {
+                    return true;
+                }
+                self.execute(Instruction::JMP(reg))
+            },
+            Instruction::JEI(target) => {
+                if !self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JNEI(target) => {
+                if self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JGI(target) => {
+                if !self.flag_gt {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JLI(target) => {
+                if !self.flag_lt {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JGEI(target) => {
+                if !(self.flag_gt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JLEI(target) => {
+                if !(self.flag_lt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JAI(target) => {
+                if !self.flag_above {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JBI(target) => {
+                if !self.flag_below {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::JUI(target) => {
+                if !self.flag_uo {
+                    return true;
+                }
+                self.execute(Instruction::JMPI(target))
+            },
+            Instruction::SWITCH(reg, n) => {
+                //table layout: default entry, then entries 0..n
+                let table = self.ip;
+                let entry = match self.reg[reg].as_i128().and_then(|i| usize::try_from(i).ok()) {
+                    Some(i) if i < n => table + 1 + i,
+                    _ => table
+                };
+                match self.code.get(entry) {
+                    Some(&Instruction::CASE(target)) => {
+                        //relative entries count from the entry itself, like a JMPI in its place
+                        self.ip = entry + 1;
+                        self.jump(target)
+                    },
+                    _ => false
+                }
+            },
+            Instruction::CASE(_) => false,
+            Instruction::CMOVE(reg1, reg2) => {
+                if !self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVNE(reg1, reg2) => {
+                if self.flag_eq {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVG(reg1, reg2) => {
+                if !self.flag_gt {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVL(reg1, reg2) => {
+                if !self.flag_lt {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVGE(reg1, reg2) => {
+                if !(self.flag_gt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVLE(reg1, reg2) => {
+                if !(self.flag_lt || self.flag_eq) {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVA(reg1, reg2) => {
+                if !self.flag_above {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVB(reg1, reg2) => {
+                if !self.flag_below {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::CMOVU(reg1, reg2) => {
+                if !self.flag_uo {
+                    return true;
+                }
+                self.execute(Instruction::MOVR(reg1, reg2))
+            },
+            Instruction::SETE(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_eq);
+                true
+            },
+            Instruction::SETNE(reg) => {
+                self.reg[reg] = Immediate::Bool(!self.flag_eq);
+                true
+            },
+            Instruction::SETG(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_gt);
+                true
+            },
+            Instruction::SETL(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_lt);
+                true
+            },
+            Instruction::SETGE(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_gt || self.flag_eq);
+                true
+            },
+            Instruction::SETLE(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_lt || self.flag_eq);
+                true
+            },
+            Instruction::SETA(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_above);
+                true
+            },
+            Instruction::SETB(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_below);
+                true
+            },
+            Instruction::SETU(reg) => {
+                self.reg[reg] = Immediate::Bool(self.flag_uo);
+                true
+            },
+            Instruction::CMP(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.compare(v1, v2);
+                true
+            },
+            Instruction::PRINTR(reg) => {
+                let val = &self.reg[reg];
+                writeln!(self.output, "Printing: {:?}", val).is_ok()
+            },
+            Instruction::PRINTV(addr) => {
+                match self.heap_slot(addr) {
+                    Some(a) => writeln!(self.output, "Printing: {:?}", self.data[a]).is_ok(),
+                    _ => false
+                }
+            },
+            Instruction::VSTORE(addr, var) => {
+                match self.heap_slot(addr) {
+                    Some(a) => {
+                        self.data[a] = var;
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::LOAD8U(reg1, reg2) => self.load(reg1, reg2, 1, |b| Immediate::U8(u8::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD8S(reg1, reg2) => self.load(reg1, reg2, 1, |b| Immediate::I8(i8::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD16U(reg1, reg2) => self.load(reg1, reg2, 2, |b| Immediate::U16(u16::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD16S(reg1, reg2) => self.load(reg1, reg2, 2, |b| Immediate::I16(i16::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD32U(reg1, reg2) => self.load(reg1, reg2, 4, |b| Immediate::U32(u32::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD32S(reg1, reg2) => self.load(reg1, reg2, 4, |b| Immediate::I32(i32::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD64U(reg1, reg2) => self.load(reg1, reg2, 8, |b| Immediate::U64(u64::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOAD64S(reg1, reg2) => self.load(reg1, reg2, 8, |b| Immediate::I64(i64::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOADF32(reg1, reg2) => self.load(reg1, reg2, 4, |b| Immediate::F32(f32::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::LOADF64(reg1, reg2) => self.load(reg1, reg2, 8, |b| Immediate::F64(f64::from_le_bytes(b.try_into().unwrap()))),
+            Instruction::STORE8(reg1, reg2) => {
+                match self.reg[reg2].as_bits128() {
+                    Some(v) => self.store(reg1, &v.to_le_bytes()[..1]),
+                    _ => false
+                }
+            },
+            Instruction::STORE16(reg1, reg2) => {
+                match self.reg[reg2].as_bits128() {
+                    Some(v) => self.store(reg1, &v.to_le_bytes()[..2]),
+                    _ => false
+                }
+            },
+            Instruction::STORE32(reg1, reg2) => {
+                match self.reg[reg2].as_bits128() {
+                    Some(v) => self.store(reg1, &v.to_le_bytes()[..4]),
+                    _ => false
+                }
+            },
+            Instruction::STORE64(reg1, reg2) => {
+                match self.reg[reg2].as_bits128() {
+                    Some(v) => self.store(reg1, &v.to_le_bytes()[..8]),
+                    _ => false
+                }
+            },
+            Instruction::STOREF32(reg1, reg2) => {
+                match self.reg[reg2] {
+                    Immediate::F32(v) => self.store(reg1, &v.to_le_bytes()),
+                    _ => false
+                }
+            },
+            Instruction::STOREF64(reg1, reg2) => {
+                match self.reg[reg2] {
+                    Immediate::F64(v) => self.store(reg1, &v.to_le_bytes()),
+                    _ => false
+                }
+            },
+            Instruction::ALLOC(reg1, reg2) => {
+                let size = match self.reg_address(reg2) {
+                    Some(size) => size,
+                    _ => return false
+                };
+                match self.allocator.alloc(size) {
+                    Some(a) => {
+                        for slot in &mut self.data[a..a + size] {
+                            *slot = Immediate::U8(0);
+                        }
+                        self.reg[reg1] = Immediate::U64(a as u64);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::FREE(reg) => {
+                let a = match self.reg_address(reg) {
+                    Some(a) => a,
+                    _ => return false
+                };
+                if self.allocator.is_freed(a) {
+                    println!("Error: double free of heap address {}{}", a, self.heap_name(a));
+                    return false;
+                }
+                self.allocator.free(a)
+            },
+            Instruction::REALLOC(reg1, reg2) => {
+                let (a, size) = match (self.reg_address(reg1), self.reg_address(reg2)) {
+                    (Some(a), Some(size)) => (a, size),
+                    _ => return false
+                };
+                let old = match self.allocator.size_of(a) {
+                    Some(old) => old,
+                    _ => return false
+                };
+                match self.allocator.realloc(a, size) {
+                    Some(new) => {
+                        if new != a {
+                            self.data.copy_within(a..a + old, new);
+                        }
+                        for slot in &mut self.data[new + old.min(size)..new + size] {
+                            *slot = Immediate::U8(0);
+                        }
+                        self.reg[reg1] = Immediate::U64(new as u64);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::NEWOBJ(reg1, reg2) => {
+                let n = match self.reg_address(reg2) {
+                    Some(n) if n <= MAX_OBJECT_FIELDS => n,
+                    _ => return false
+                };
+                if self.objects.should_collect(n) {
+                    self.collect_garbage();
+                }
+                let r = self.objects.alloc(Object::Record(vec![Immediate::U8(0); n]));
+                self.reg[reg1] = Immediate::Ref(r);
+                true
+            },
+            Instruction::GETFIELD(reg1, reg2, n) => {
+                let field = self.object_ref(reg2).and_then(|r| self.objects.get(r)).and_then(|object| object.fields().get(n));
+                match field {
+                    Some(&v) => {
+                        self.reg[reg1] = v;
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::SETFIELD(reg1, n, reg2) => {
+                let v = self.reg[reg2];
+                let r = match self.object_ref(reg1) {
+                    Some(r) => r,
+                    _ => return false
+                };
+                match self.objects.get_mut(r) {
+                    Some(Object::Record(fields)) if n < fields.len() => {
+                        fields[n] = v;
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::LDSTR(reg, n) => {
+                match self.strings.get(n).cloned() {
+                    Some(text) => {
+                        self.reg[reg] = self.new_string(text);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::STRCAT(reg1, reg2, reg3) => {
+                let text = match (self.string(reg2), self.string(reg3)) {
+                    (Some(a), Some(b)) => format!("{}{}", a, b),
+                    _ => return false
+                };
+                self.reg[reg1] = self.new_string(text);
+                true
+            },
+            Instruction::STRLEN(reg1, reg2) => {
+                match self.string(reg2) {
+                    Some(text) => {
+                        self.reg[reg1] = Immediate::U64(text.chars().count() as u64);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::SUBSTR(reg1, reg2, reg3, reg4) => {
+                let (start, len) = match (self.reg_address(reg3), self.reg_address(reg4)) {
+                    (Some(start), Some(len)) => (start, len),
+                    _ => return false
+                };
+                let text = match self.string(reg2) {
+                    Some(text) if start.saturating_add(len) <= text.chars().count() => text.chars().skip(start).take(len).collect(),
+                    _ => return false
+                };
+                self.reg[reg1] = self.new_string(text);
+                true
+            },
+            Instruction::STRCMP(reg1, reg2) => {
+                let ord = match (self.string(reg1), self.string(reg2)) {
+                    (Some(a), Some(b)) => a.cmp(b),
+                    _ => return false
+                };
+                self.set_flags(Some(ord), Some(ord));
+                true
+            },
+            Instruction::CHARAT(reg1, reg2, reg3) => {
+                let c = match (self.string(reg2), self.reg_address(reg3)) {
+                    (Some(text), Some(i)) => text.chars().nth(i),
+                    _ => None
+                };
+                match c {
+                    Some(c) => {
+                        self.reg[reg1] = Immediate::Char(c);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::TOSTR(reg1, reg2) => {
+                let text = match self.reg[reg2] {
+                    Immediate::F32(v) => v.to_string(),
+                    Immediate::F64(v) => v.to_string(),
+                    Immediate::Bool(v) => v.to_string(),
+                    Immediate::Char(v) => v.to_string(),
+                    Immediate::U128(v) => v.to_string(),
+                    v => match v.as_i128() {
+                        Some(v) => v.to_string(),
+                        _ => return false
+                    }
+                };
+                self.reg[reg1] = self.new_string(text);
+                true
+            },
+            Instruction::PARSEI(reg1, reg2) => {
+                match self.string(reg2).map(|text| text.trim().parse::<i64>()) {
+                    Some(Ok(v)) => {
+                        self.reg[reg1] = Immediate::I64(v);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::PARSEF(reg1, reg2) => {
+                match self.string(reg2).map(|text| text.trim().parse::<f64>()) {
+                    Some(Ok(v)) => {
+                        self.reg[reg1] = Immediate::F64(v);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::PRINTS(reg) => {
+                let text = match self.string(reg) {
+                    Some(text) => text.to_string(),
+                    _ => return false
+                };
+                self.output.write_all(text.as_bytes()).and_then(|_| self.output.flush()).is_ok()
+            },
+            Instruction::NEWARRAY(reg1, var, reg2) => {
+                let len = match self.reg_address(reg2) {
+                    Some(len) if len <= MAX_OBJECT_FIELDS => len,
+                    _ => return false
+                };
+                if self.objects.should_collect(len) {
+                    self.collect_garbage();
+                }
+                let r = self.objects.alloc(Object::Array(var, vec![var; len]));
+                self.reg[reg1] = Immediate::Ref(r);
+                true
+            },
+            Instruction::ALOAD(reg1, reg2, reg3) => {
+                let (items, i) = match (self.array(reg2), self.reg_address(reg3)) {
+                    (Some((_, items)), Some(i)) => (items, i),
+                    _ => return false
+                };
+                if !Self::array_bounds(i, 1, items.len()) {
+                    return false;
+                }
+                self.reg[reg1] = items[i];
+                true
+            },
+            Instruction::ASTORE(reg1, reg2, reg3) => {
+                let v = self.reg[reg3];
+                let i = match self.reg_address(reg2) {
+                    Some(i) => i,
+                    _ => return false
+                };
+                match self.array_mut(reg1) {
+                    Some((elem, items)) if Self::element_fits(elem, v) => {
+                        if !Self::array_bounds(i, 1, items.len()) {
+                            return false;
+                        }
+                        items[i] = v;
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::ALEN(reg1, reg2) => {
+                match self.array(reg2) {
+                    Some((_, items)) => {
+                        self.reg[reg1] = Immediate::U64(items.len() as u64);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::ACOPY(reg1, reg2, reg3, reg4, reg5) => {
+                let (dst_index, src_index, count) = match (self.reg_address(reg2), self.reg_address(reg4), self.reg_address(reg5)) {
+                    (Some(d), Some(s), Some(n)) => (d, s, n),
+                    _ => return false
+                };
+                let (src_elem, src) = match self.array(reg3) {
+                    Some((elem, items)) if Self::array_bounds(src_index, count, items.len()) => (elem, items[src_index..src_index + count].to_vec()),
+                    _ => return false
+                };
+                match self.array_mut(reg1) {
+                    Some((elem, items)) if mem::discriminant(&elem) == mem::discriminant(&src_elem) => {
+                        if !Self::array_bounds(dst_index, count, items.len()) {
+                            return false;
+                        }
+                        items[dst_index..dst_index + count].copy_from_slice(&src);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::LOADMOD(reg1, reg2) => {
+                let name = match self.string(reg2) {
+                    Some(name) => name.to_string(),
+                    _ => return false
+                };
+                self.reg[reg1] = Immediate::Bool(self.load_module(&name));
+                true
+            },
+            Instruction::LOADSYM(reg1, reg2) => {
+                let addr = match self.string(reg2) {
+                    Some(name) => match self.symbols.get(name) {
+                        Some(&(_, addr)) => addr,
+                        _ => {
+                            println!("Error: undefined symbol {}", name);
+                            return false;
+                        }
+                    },
+                    _ => return false
+                };
+                self.reg[reg1] = Immediate::U64(addr as u64);
+                true
+            },
+            Instruction::AFILL(reg1, reg2) => {
+                let v = self.reg[reg2];
+                match self.array_mut(reg1) {
+                    Some((elem, items)) if Self::element_fits(elem, v) => {
+                        for item in items.iter_mut() {
+                            *item = v;
+                        }
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::VLOAD(addr) => {
+                match self.heap_slot(addr) {
+                    Some(a) => {
+                        self.stack.push(self.data[a]);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::VSTORER(addr, reg) => {
+                match self.heap_slot(addr) {
+                    Some(a) => {
+                        self.data[a] = self.reg[reg];
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::VLOADR(reg, addr) => {
+                match self.heap_slot(addr) {
+                    Some(a) => {
+                        self.reg[reg] = self.data[a];
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::ADD(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(add(v1, v2))
+            },
+            Instruction::SUB(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(sub(v1, v2))
+            },
+            Instruction::MUL(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(mul(v1, v2))
+            },
+            Instruction::DIV(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(div(v1, v2))
+            },
+            Instruction::VPUSH(var) => {
+                self.stack.push(var);
+                true
+            },
+            Instruction::VPUSHR(reg) => {
+                self.stack.push(self.reg[reg]);
+                true
+            },
+            Instruction::VPOP(reg) => {
+                match self.pop() {
+                    Some(v) => {
+                        self.reg[reg] = v;
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::DUP() => {
+                match self.stack.last() {
+                    Some(&v) => {
+                        self.stack.push(v);
+                        true
+                    },
+                    _ => false
+                }
+            },
+            Instruction::SWAP() => {
+                let len = self.stack.len();
+                if self.stack_depth() < 2 {
+                    return false;
+                }
+                self.stack.swap(len - 1, len - 2);
+                true
+            },
+            Instruction::OVER() => self.execute(Instruction::PICK(1)),
+            Instruction::ROT() => {
+                let len = self.stack.len();
+                if self.stack_depth() < 3 {
+                    return false;
+                }
+                let v = self.stack.remove(len - 3);
+                self.stack.push(v);
+                true
+            },
+            Instruction::DROP() => self.pop().is_some(),
+            Instruction::PICK(n) => {
+                let len = self.stack.len();
+                if n >= len {
+                    return false;
+                }
+                self.stack.push(self.stack[len - 1 - n]);
+                true
+            },
+            Instruction::SADD() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(add(v1, v2)),
+                    _ => false
+                }
+            },
+            Instruction::SSUB() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(sub(v1, v2)),
+                    _ => false
+                }
+            },
+            Instruction::SMUL() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(mul(v1, v2)),
+                    _ => false
+                }
+            },
+            Instruction::SDIV() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(div(v1, v2)),
+                    _ => false
+                }
+            },
+            Instruction::SAND() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(and(v1, v2)),
+                    _ => false
+                }
             },
-            Instruction::JMP(reg) => {
-                match self.reg[reg] {
-                    Immediate::U8(v) => {
-                        self.ip = v as Address;
-                    true
-                    }
-                    Immediate::U16(v) => {
-                        self.ip = v as Address;
-                        true
-                    }
+            Instruction::SOR() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(or(v1, v2)),
                     _ => false
                 }
             },
-            Instruction::JE(reg) => {
-                if !self.flag_eq {
-                    return true;
+            Instruction::SXOR() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(xor(v1, v2)),
+                    _ => false
                 }
-                self.execute(Instruction::JMP(reg))
             },
-            Instruction::JNE(reg) => {
-                if self.flag_eq {
-                    return true;
+            Instruction::SSHR() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(shr(v1, v2)),
+                    _ => false
                 }
-                self.execute(Instruction::JMP(reg))
             },
-            Instruction::JG(reg) => {
-                if !self.flag_gt {
-                    return true;
+            Instruction::SSHL() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => self.push_result(shl(v1, v2)),
+                    _ => false
                 }
-                self.execute(Instruction::JMP(reg))
             },
-            Instruction::JL(reg) => {
-                if self.flag_gt {
-                    return true;
+            Instruction::SCMP() => {
+                match self.pop_operands() {
+                    Some((v1, v2)) => {
+                        self.compare(v1, v2);
+                        true
+                    },
+                    _ => false
                 }
-                self.execute(Instruction::JMP(reg))
             },
-            Instruction::CMP(reg1, reg2) => {
-                let v1 = self.reg[reg1];
-                let v2 = self.reg[reg2];
-                self.flag_eq = v1 == v2;
-                self.flag_gt = v1 > v2;
-                true
+            Instruction::ADD3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, add(v1, v2))
             },
-            Instruction::PRINTR(reg) => {
-                let val = &self.reg[reg];
-                println!("Printing: {:?}", val);
-                true
+            Instruction::SUB3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, sub(v1, v2))
             },
-            Instruction::PRINTV(addr) => {
-                let val = &self.data[addr];
-                println!("Printing: {:?}", val);
-                true
+            Instruction::MUL3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, mul(v1, v2))
             },
-            Instruction::VSTORE(addr, var) => {
-                self.data[addr] = var;
-                true
+            Instruction::DIV3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, div(v1, v2))
             },
-            Instruction::VLOAD(addr) => {
-                self.stack.push(self.data[addr]);
-                true
+            Instruction::AND3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, and(v1, v2))
             },
-            Instruction::VSTORER(addr, reg) => {
-                self.data[addr] = self.reg[reg];
-                true
+            Instruction::OR3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, or(v1, v2))
             },
-            Instruction::VLOADR(reg, addr) => {
-                self.reg[reg] = self.data[addr];
-                true
+            Instruction::XOR3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, xor(v1, v2))
             },
-            Instruction::ADD(reg1, reg2) => {
-                let v1 = self.reg[reg1];
-                let v2 = self.reg[reg2];
-                if let Ok(r) = v1 + v2 {
-                    self.stack.push(r);
-                    true
-                } else {
-                    false
-                }
+            Instruction::SHR3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, shr(v1, v2))
             },
-            Instruction::SUB(reg1, reg2) => {
-                let v1 = self.reg[reg1];
-                let v2 = self.reg[reg2];
-                if let Ok(r) = v1 - v2 {
-                    self.stack.push(r);
-                    true
-                } else {
-                    false
+            Instruction::SHL3(reg1, reg2, reg3) => {
+                let v1 = self.reg[reg2];
+                let v2 = self.reg[reg3];
+                self.set_result(reg1, shl(v1, v2))
+            },
+            Instruction::ADDI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, add(v1, var))
+            },
+            Instruction::SUBI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, sub(v1, var))
+            },
+            Instruction::MULI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, mul(v1, var))
+            },
+            Instruction::DIVI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, div(v1, var))
+            },
+            Instruction::ANDI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, and(v1, var))
+            },
+            Instruction::ORI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, or(v1, var))
+            },
+            Instruction::XORI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, xor(v1, var))
+            },
+            Instruction::SHRI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, shr(v1, var))
+            },
+            Instruction::SHLI(reg, var) => {
+                let v1 = self.reg[reg];
+                self.set_result(reg, shl(v1, var))
+            },
+            Instruction::CALL(reg) => self.push_frame() && self.execute(Instruction::JMP(reg)),
+            Instruction::CALLI(target) => self.push_frame() && self.jump(target),
+            Instruction::PUSHA(reg1, reg2) => {
+                if reg1 > reg2 || reg2 >= self.reg.len() {
+                    return false;
                 }
+                self.stack.extend_from_slice(&self.reg[reg1..=reg2]);
+                true
             },
-            Instruction::MUL(reg1, reg2) => {
-                let v1 = self.reg[reg1];
-                let v2 = self.reg[reg2];
-                match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u*v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u*v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u*v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u*v));
-                    },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u*v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u*v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u*v));
-                    },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u*v));
-                    },
-                    (Immediate::F32(v), Immediate::F32(u)) => {
-                        self.stack.push(Immediate::F32(u*v));
-                    },
-                    (Immediate::F64(v), Immediate::F64(u)) => {
-                        self.stack.push(Immediate::F64(u*v));
-                    },
-                    _ => {return false;}
+            Instruction::POPA(reg1, reg2) => {
+                if reg1 > reg2 || reg2 >= self.reg.len() || self.stack_depth() < reg2 - reg1 + 1 {
+                    return false;
                 }
+                let start = self.stack.len() - (reg2 - reg1 + 1);
+                self.reg[reg1..=reg2].copy_from_slice(&self.stack[start..]);
+                self.stack.truncate(start);
                 true
             },
-            Instruction::DIV(reg1, reg2) => {
-                let v1 = self.reg[reg1];
-                let v2 = self.reg[reg2];
-                match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u/v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u/v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u/v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u/v));
-                    },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u/v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u/v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u/v));
-                    },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u/v));
-                    },
-                    (Immediate::F32(v), Immediate::F32(u)) => {
-                        self.stack.push(Immediate::F32(u/v));
+            Instruction::TAILCALL(reg, n) => self.reuse_frame(n) && self.execute(Instruction::JMP(reg)),
+            Instruction::TAILCALLI(target, n) => self.reuse_frame(n) && self.jump(target),
+            Instruction::ENTER(n) => {
+                match self.frames.last_mut() {
+                    Some(frame) => {
+                        frame.locals = vec![Immediate::U8(0); n];
+                        true
                     },
-                    (Immediate::F64(v), Immediate::F64(u)) => {
-                        self.stack.push(Immediate::F64(u/v));
+                    _ => false
+                }
+            },
+            Instruction::LEAVE() => {
+                match self.frames.last_mut() {
+                    Some(frame) => {
+                        frame.locals.clear();
+                        true
                     },
-                    _ => {return false;}
+                    _ => false
                 }
-                true
             },
-            Instruction::VPUSH(var) => {
-                self.stack.push(var);
-                true
+            Instruction::LOADLOCAL(reg, n) => {
+                match self.frames.last().and_then(|frame| frame.locals.get(n)) {
+                    Some(&v) => {
+                        self.reg[reg] = v;
+                        true
+                    },
+                    _ => false
+                }
             },
-            Instruction::VPUSHR(reg) => {
-                self.stack.push(self.reg[reg]);
-                true
+            Instruction::STORELOCAL(n, reg) => {
+                let v = self.reg[reg];
+                match self.frames.last_mut().and_then(|frame| frame.locals.get_mut(n)) {
+                    Some(slot) => {
+                        *slot = v;
+                        true
+                    },
+                    _ => false
+                }
             },
-            Instruction::VPOP(reg) => {
-                match self.stack.pop() {
-                    Some(v) => {
+            Instruction::LOADARG(reg, n) => {
+                let fp = match self.frames.last() {
+                    Some(frame) => frame.frame_pointer,
+                    _ => return false
+                };
+                match fp.checked_sub(n + 1).and_then(|i| self.stack.get(i)) {
+                    Some(&v) => {
                         self.reg[reg] = v;
                         true
                     },
                     _ => false
                 }
             },
-            Instruction::CALL(reg) => {
-                self.stack.push(Immediate::U16(self.ip as u16 + 1));
-                self.execute(Instruction::JMP(reg))
-            },
             Instruction::OR(reg1, reg2) => {
                 let v1 = self.reg[reg1];
                 let v2 = self.reg[reg2];
-                match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u|v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u|v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u|v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u|v));
-                    },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u|v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u|v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u|v));
+                self.push_result(or(v1, v2))
+            },
+            Instruction::XOR(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(xor(v1, v2))
+            },
+            Instruction::SHR(reg1, v2) => {
+                let v1 = self.reg[reg1];
+                self.push_result(shr(v1, v2))
+            },
+            Instruction::SHL(reg1, v2) => {
+                let v1 = self.reg[reg1];
+                self.push_result(shl(v1, v2))
+            },
+            Instruction::AND(reg1, reg2) => {
+                let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
+                self.push_result(and(v1, v2))
+            },
+            Instruction::RET() => {
+                match self.frames.pop() {
+                    Some(frame) => {
+                        for (r, v) in frame.saved {
+                            if self.callee_saved_mode == CalleeSaved::Check && self.reg[r] != v {
+                                println!("Warning: callee-saved register {} changed from {:?} to {:?}", r, v, self.reg[r]);
+                            }
+                            if self.callee_saved_mode == CalleeSaved::Preserve {
+                                self.reg[r] = v;
+                            }
+                        }
+                        self.stack.truncate(frame.frame_pointer);
+                        self.ip = frame.return_address;
+                        true
+                    }
+                    _ => false
+                }
+            }
+            Instruction::FSQRT(reg) => {
+                match self.reg[reg] {
+                    Immediate::F32(v) => {
+                        self.stack.push(Immediate::F32(v.sqrt()));
                     },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u|v));
+                    Immediate::F64(v) => {
+                        self.stack.push(Immediate::F64(v.sqrt()));
                     },
                     _ => {return false;}
                 }
                 true
             },
-            Instruction::XOR(reg1, reg2) => {
+            Instruction::FMIN(reg1, reg2) => {
                 let v1 = self.reg[reg1];
                 let v2 = self.reg[reg2];
                 match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u^v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u^v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u^v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u^v));
-                    },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u^v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u^v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u^v));
+                    (Immediate::F32(v), Immediate::F32(u)) => {
+                        self.stack.push(Immediate::F32(v.min(u)));
                     },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u^v));
+                    (Immediate::F64(v), Immediate::F64(u)) => {
+                        self.stack.push(Immediate::F64(v.min(u)));
                     },
                     _ => {return false;}
                 }
                 true
             },
-            Instruction::SHR(reg1, v2) => {
+            Instruction::FMAX(reg1, reg2) => {
                 let v1 = self.reg[reg1];
+                let v2 = self.reg[reg2];
                 match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u>>v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u>>v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u>>v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u>>v));
-                    },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u>>v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u>>v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u>>v));
+                    (Immediate::F32(v), Immediate::F32(u)) => {
+                        self.stack.push(Immediate::F32(v.max(u)));
                     },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u>>v));
+                    (Immediate::F64(v), Immediate::F64(u)) => {
+                        self.stack.push(Immediate::F64(v.max(u)));
                     },
                     _ => {return false;}
                 }
                 true
             },
-            Instruction::SHL(reg1, v2) => {
-                let v1 = self.reg[reg1];
-                match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u<<v));
+            Instruction::FLOOR(reg) => {
+                match self.reg[reg] {
+                    Immediate::F32(v) => {
+                        self.stack.push(Immediate::F32(v.floor()));
                     },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u<<v));
+                    Immediate::F64(v) => {
+                        self.stack.push(Immediate::F64(v.floor()));
                     },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u<<v));
+                    _ => {return false;}
+                }
+                true
+            },
+            Instruction::CEIL(reg) => {
+                match self.reg[reg] {
+                    Immediate::F32(v) => {
+                        self.stack.push(Immediate::F32(v.ceil()));
                     },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u<<v));
+                    Immediate::F64(v) => {
+                        self.stack.push(Immediate::F64(v.ceil()));
                     },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u<<v));
+                    _ => {return false;}
+                }
+                true
+            },
+            Instruction::TRUNC(reg) => {
+                match self.reg[reg] {
+                    Immediate::F32(v) => {
+                        self.stack.push(Immediate::F32(v.trunc()));
                     },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u<<v));
+                    Immediate::F64(v) => {
+                        self.stack.push(Immediate::F64(v.trunc()));
                     },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u<<v));
+                    _ => {return false;}
+                }
+                true
+            },
+            Instruction::ROUND(reg) => {
+                match self.reg[reg] {
+                    Immediate::F32(v) => {
+                        self.stack.push(Immediate::F32(v.round()));
                     },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u<<v));
+                    Immediate::F64(v) => {
+                        self.stack.push(Immediate::F64(v.round()));
                     },
                     _ => {return false;}
                 }
                 true
             },
-            Instruction::AND(reg1, reg2) => {
+            Instruction::FMA(reg1, reg2, reg3) => {
                 let v1 = self.reg[reg1];
                 let v2 = self.reg[reg2];
-                match (v1, v2){
-                    (Immediate::U8(v), Immediate::U8(u)) => {
-                        self.stack.push(Immediate::U8(u&v));
-                    },
-                    (Immediate::I8(v), Immediate::I8(u)) => {
-                        self.stack.push(Immediate::I8(u&v));
-                    },
-                    (Immediate::U16(v), Immediate::U16(u)) => {
-                        self.stack.push(Immediate::U16(u&v));
-                    },
-                    (Immediate::I16(v), Immediate::I16(u)) => {
-                        self.stack.push(Immediate::I16(u&v));
+                let v3 = self.reg[reg3];
+                match (v1, v2, v3){
+                    (Immediate::F32(v), Immediate::F32(u), Immediate::F32(w)) => {
+                        self.stack.push(Immediate::F32(v.mul_add(u, w)));
                     },
-                    (Immediate::U32(v), Immediate::U32(u)) => {
-                        self.stack.push(Immediate::U32(u&v));
-                    },
-                    (Immediate::I32(v), Immediate::I32(u)) => {
-                        self.stack.push(Immediate::I32(u&v));
-                    },
-                    (Immediate::U64(v), Immediate::U64(u)) => {
-                        self.stack.push(Immediate::U64(u&v));
-                    },
-                    (Immediate::I64(v), Immediate::I64(u)) => {
-                        self.stack.push(Immediate::I64(u&v));
+                    (Immediate::F64(v), Immediate::F64(u), Immediate::F64(w)) => {
+                        self.stack.push(Immediate::F64(v.mul_add(u, w)));
                     },
                     _ => {return false;}
                 }
                 true
             },
-            Instruction::RET() => {
-                match self.stack.pop() {
-                    Some(v) => {
-                        match v {
-                            Immediate::U8(u) => {
-                                self.ip = u as usize;
-                                true
-                            }
-                            Immediate::U16(u) => {
-                                self.ip = u as usize;
-                                true
-                            }
-                            _ => false
-                        }
-                    }
-                    _ => false
-                }
-            }
             Instruction::HALT() => {
                 self.is_executing = false;
                 true
@@ -402,11 +1528,280 @@ impl VirtualMachine {
     }
 
     pub fn cpu(&mut self) {
-        for i in 0..self.code.len() {
+        if let Err((i, e)) = verify(&self.code, self.reg.len()) {
+            panic!("Verification failed at instruction #{}{}: {:?} in {:?}", i, self.describe(i), e, self.code[i])
+        }
+        if self.callee_saved_mode != CalleeSaved::Ignore {
+            for (i, r) in callee_saved_violations(&self.code, &self.callee_saved) {
+                println!("Warning: instruction #{}{} overwrites callee-saved register {} without saving it: {:?}", i, self.describe(i), r, self.code[i]);
+            }
+        }
+        //ip already points past the instruction being executed, so control flow instructions overwrite it
+        //and CALL saves the address of the instruction after the call
+        self.is_executing = true;
+        while self.is_executing && self.ip < self.code.len() {
+            let i = self.ip;
+            self.ip += 1;
             let result = self.execute(self.code[i]);
             if !result {
-                panic!("Failed to execute instruction at instruction #{}: {:?}", i, self.code[i])
+                panic!("Failed to execute instruction at instruction #{}{}: {:?}", i, self.describe(i), self.code[i])
             }
         }
     }
 }
+
+//arithmetic helpers shared by the register and stack forms of each instruction.
+//integer overflow, division by zero and oversized shifts give None, which faults the instruction
+fn add(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_add(u).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_add(u).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_add(u).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_add(u).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_add(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_add(u).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => v.checked_add(u).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => v.checked_add(u).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => v.checked_add(u).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => v.checked_add(u).map(Immediate::I128),
+        (Immediate::F32(v), Immediate::F32(u)) => Some(Immediate::F32(v + u)),
+        (Immediate::F64(v), Immediate::F64(u)) => Some(Immediate::F64(v + u)),
+        _ => None
+    }
+}
+
+fn sub(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_sub(u).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_sub(u).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_sub(u).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_sub(u).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_sub(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_sub(u).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => v.checked_sub(u).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => v.checked_sub(u).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => v.checked_sub(u).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => v.checked_sub(u).map(Immediate::I128),
+        (Immediate::F32(v), Immediate::F32(u)) => Some(Immediate::F32(v - u)),
+        (Immediate::F64(v), Immediate::F64(u)) => Some(Immediate::F64(v - u)),
+        _ => None
+    }
+}
+
+fn mul(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_mul(u).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_mul(u).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_mul(u).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_mul(u).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_mul(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_mul(u).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => v.checked_mul(u).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => v.checked_mul(u).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => v.checked_mul(u).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => v.checked_mul(u).map(Immediate::I128),
+        (Immediate::F32(v), Immediate::F32(u)) => Some(Immediate::F32(v * u)),
+        (Immediate::F64(v), Immediate::F64(u)) => Some(Immediate::F64(v * u)),
+        _ => None
+    }
+}
+
+fn div(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_div(u).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_div(u).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_div(u).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_div(u).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_div(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_div(u).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => v.checked_div(u).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => v.checked_div(u).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => v.checked_div(u).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => v.checked_div(u).map(Immediate::I128),
+        (Immediate::F32(v), Immediate::F32(u)) => Some(Immediate::F32(v / u)),
+        (Immediate::F64(v), Immediate::F64(u)) => Some(Immediate::F64(v / u)),
+        _ => None
+    }
+}
+
+fn and(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => Some(Immediate::U8(v & u)),
+        (Immediate::I8(v), Immediate::I8(u)) => Some(Immediate::I8(v & u)),
+        (Immediate::U16(v), Immediate::U16(u)) => Some(Immediate::U16(v & u)),
+        (Immediate::I16(v), Immediate::I16(u)) => Some(Immediate::I16(v & u)),
+        (Immediate::U32(v), Immediate::U32(u)) => Some(Immediate::U32(v & u)),
+        (Immediate::I32(v), Immediate::I32(u)) => Some(Immediate::I32(v & u)),
+        (Immediate::U64(v), Immediate::U64(u)) => Some(Immediate::U64(v & u)),
+        (Immediate::I64(v), Immediate::I64(u)) => Some(Immediate::I64(v & u)),
+        (Immediate::U128(v), Immediate::U128(u)) => Some(Immediate::U128(v & u)),
+        (Immediate::I128(v), Immediate::I128(u)) => Some(Immediate::I128(v & u)),
+        (Immediate::Bool(v), Immediate::Bool(u)) => Some(Immediate::Bool(v & u)),
+        _ => None
+    }
+}
+
+fn or(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => Some(Immediate::U8(v | u)),
+        (Immediate::I8(v), Immediate::I8(u)) => Some(Immediate::I8(v | u)),
+        (Immediate::U16(v), Immediate::U16(u)) => Some(Immediate::U16(v | u)),
+        (Immediate::I16(v), Immediate::I16(u)) => Some(Immediate::I16(v | u)),
+        (Immediate::U32(v), Immediate::U32(u)) => Some(Immediate::U32(v | u)),
+        (Immediate::I32(v), Immediate::I32(u)) => Some(Immediate::I32(v | u)),
+        (Immediate::U64(v), Immediate::U64(u)) => Some(Immediate::U64(v | u)),
+        (Immediate::I64(v), Immediate::I64(u)) => Some(Immediate::I64(v | u)),
+        (Immediate::U128(v), Immediate::U128(u)) => Some(Immediate::U128(v | u)),
+        (Immediate::I128(v), Immediate::I128(u)) => Some(Immediate::I128(v | u)),
+        (Immediate::Bool(v), Immediate::Bool(u)) => Some(Immediate::Bool(v | u)),
+        _ => None
+    }
+}
+
+fn xor(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => Some(Immediate::U8(v ^ u)),
+        (Immediate::I8(v), Immediate::I8(u)) => Some(Immediate::I8(v ^ u)),
+        (Immediate::U16(v), Immediate::U16(u)) => Some(Immediate::U16(v ^ u)),
+        (Immediate::I16(v), Immediate::I16(u)) => Some(Immediate::I16(v ^ u)),
+        (Immediate::U32(v), Immediate::U32(u)) => Some(Immediate::U32(v ^ u)),
+        (Immediate::I32(v), Immediate::I32(u)) => Some(Immediate::I32(v ^ u)),
+        (Immediate::U64(v), Immediate::U64(u)) => Some(Immediate::U64(v ^ u)),
+        (Immediate::I64(v), Immediate::I64(u)) => Some(Immediate::I64(v ^ u)),
+        (Immediate::U128(v), Immediate::U128(u)) => Some(Immediate::U128(v ^ u)),
+        (Immediate::I128(v), Immediate::I128(u)) => Some(Immediate::I128(v ^ u)),
+        (Immediate::Bool(v), Immediate::Bool(u)) => Some(Immediate::Bool(v ^ u)),
+        _ => None
+    }
+}
+
+fn shr(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_shr(u as u32).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_shr(u as u32).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_shr(u as u32).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_shr(u as u32).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_shr(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_shr(u as u32).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shr(u)).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shr(u)).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shr(u)).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shr(u)).map(Immediate::I128),
+        _ => None
+    }
+}
+
+fn shl(v1: Immediate, v2: Immediate) -> Option<Immediate> {
+    match (v1, v2) {
+        (Immediate::U8(v), Immediate::U8(u)) => v.checked_shl(u as u32).map(Immediate::U8),
+        (Immediate::I8(v), Immediate::I8(u)) => v.checked_shl(u as u32).map(Immediate::I8),
+        (Immediate::U16(v), Immediate::U16(u)) => v.checked_shl(u as u32).map(Immediate::U16),
+        (Immediate::I16(v), Immediate::I16(u)) => v.checked_shl(u as u32).map(Immediate::I16),
+        (Immediate::U32(v), Immediate::U32(u)) => v.checked_shl(u).map(Immediate::U32),
+        (Immediate::I32(v), Immediate::I32(u)) => v.checked_shl(u as u32).map(Immediate::I32),
+        (Immediate::U64(v), Immediate::U64(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shl(u)).map(Immediate::U64),
+        (Immediate::I64(v), Immediate::I64(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shl(u)).map(Immediate::I64),
+        (Immediate::U128(v), Immediate::U128(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shl(u)).map(Immediate::U128),
+        (Immediate::I128(v), Immediate::I128(u)) => u32::try_from(u).ok().and_then(|u| v.checked_shl(u)).map(Immediate::I128),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //counts the argument down to zero, recursing through `call` at instruction 10
+    fn countdown(call: Instruction) -> VirtualMachine {
+        let code = vec![
+            Instruction::VPUSH(Immediate::I64(1000)),
+            Instruction::CALLI(Target::Absolute(4)),
+            Instruction::DROP(),
+            Instruction::HALT(),
+            Instruction::LOADARG(0, 0),
+            Instruction::MOV(1, Immediate::I64(0)),
+            Instruction::CMP(0, 1),
+            Instruction::JEI(Target::Absolute(12)),
+            Instruction::SUBI(0, Immediate::I64(1)),
+            Instruction::VPUSHR(0),
+            call,
+            Instruction::DROP(),
+            Instruction::RET(),
+        ];
+        let mut vm = VirtualMachine::from_program(Program { code, ..Program::default() }, 16, 8).unwrap();
+        vm.set_stack_limit(1);
+        vm
+    }
+
+    #[test]
+    fn tail_recursion_runs_in_one_frame() {
+        let mut vm = countdown(Instruction::TAILCALLI(Target::Absolute(4), 1));
+        vm.cpu();
+        assert_eq!(vm.reg[0], Immediate::I64(0));
+        assert!(vm.frames.is_empty());
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to execute instruction at instruction #10")]
+    fn plain_recursion_hits_the_stack_limit() {
+        countdown(Instruction::CALLI(Target::Absolute(4))).cpu();
+    }
+
+    #[test]
+    fn wide_indices_do_not_wrap() {
+        let code = vec![
+            Instruction::MOV(1, Immediate::U128(1 << 64)),
+            Instruction::SWITCH(1, 1),
+            Instruction::CASE(Target::Absolute(4)),
+            Instruction::CASE(Target::Absolute(6)),
+            Instruction::MOV(0, Immediate::U8(1)),
+            Instruction::HALT(),
+            Instruction::MOV(0, Immediate::U8(2)),
+            Instruction::HALT(),
+        ];
+        let mut vm = VirtualMachine::from_program(Program { code, ..Program::default() }, 16, 8).unwrap();
+        vm.cpu();
+        assert_eq!(vm.reg[0], Immediate::U8(1));
+        assert_eq!(vm.heap_slot(HeapAddress::Indirect(1)), None);
+        assert_eq!(vm.memory_range(1, 1), None);
+        vm.reg[1] = Immediate::I128(i128::MAX);
+        vm.reg[2] = Immediate::I128(i128::MAX);
+        assert_eq!(vm.heap_slot(HeapAddress::Offset(1, 1)), None);
+        assert_eq!(vm.heap_slot(HeapAddress::Indexed(1, 2, 2)), None);
+    }
+
+    #[test]
+    fn arrays_of_references() {
+        let code = vec![
+            Instruction::MOV(1, Immediate::U8(2)),
+            Instruction::NEWARRAY(0, Immediate::None(), 1),
+            Instruction::LDSTR(2, 0),
+            Instruction::MOV(3, Immediate::U8(1)),
+            Instruction::ASTORE(0, 3, 2),
+            Instruction::HALT(),
+        ];
+        let program = Program { code, strings: vec!["text".to_string()], ..Program::default() };
+        let mut vm = VirtualMachine::from_program(program, 16, 8).unwrap();
+        vm.cpu();
+        let (_, items) = vm.array(0).unwrap();
+        assert_eq!(items[0], Immediate::None());
+        assert_eq!(items[1], vm.reg[2]);
+        vm.reg[4] = Immediate::U8(7);
+        assert!(!vm.execute(Instruction::ASTORE(0, 3, 4)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to execute instruction at instruction #3")]
+    fn callee_cannot_pop_its_arguments() {
+        let code = vec![
+            Instruction::VPUSH(Immediate::I64(1)),
+            Instruction::CALLI(Target::Absolute(3)),
+            Instruction::HALT(),
+            Instruction::DROP(),
+            Instruction::LOADARG(0, 0),
+            Instruction::RET(),
+        ];
+        VirtualMachine::from_program(Program { code, ..Program::default() }, 16, 8).unwrap().cpu();
+    }
+}